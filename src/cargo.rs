@@ -0,0 +1,421 @@
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Options that are passed on to every built-in cargo command that builds the crate
+#[derive(Debug, Default, Clone)]
+pub struct CargoOptions {
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+    pub release: bool,
+    /// The cargo profile to build with, like --profile for cargo
+    pub profile: Option<String>,
+    /// The target triple to build for
+    pub target: Option<String>,
+}
+
+impl CargoOptions {
+    /// The arguments to add after the cargo subcommand
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push("--features".into());
+            args.push(self.features.join(","));
+        }
+        if self.all_features {
+            args.push("--all-features".into());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".into());
+        }
+        if self.release {
+            args.push("--release".into());
+        }
+        if let Some(profile) = &self.profile {
+            args.push("--profile".into());
+            args.push(profile.clone());
+        }
+        if let Some(target) = &self.target {
+            args.push("--target".into());
+            args.push(target.clone());
+        }
+        args
+    }
+}
+
+/// A package in the workspace of the crate directory
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    /// The directory with the Cargo.toml of the package
+    pub dir: PathBuf,
+    /// The names of the packages it depends on, including the ones outside the workspace
+    pub dependencies: Vec<String>,
+    /// The package has a library, which is where the doc tests are
+    pub has_lib: bool,
+}
+
+/// The packages in the workspace of the crate directory, and where it is built
+#[derive(Debug, Default)]
+pub struct Workspace {
+    pub packages: Vec<Package>,
+    pub target_dir: Option<PathBuf>,
+}
+
+/// The parts of the output from cargo metadata that are used
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct MetadataPackage {
+    name: String,
+    manifest_path: PathBuf,
+    #[serde(default)]
+    dependencies: Vec<MetadataDependency>,
+    #[serde(default)]
+    targets: Vec<MetadataTarget>,
+}
+
+#[derive(Deserialize)]
+struct MetadataTarget {
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MetadataDependency {
+    name: String,
+}
+
+/// Find the packages in the workspace of the crate directory using cargo metadata
+///
+/// This is empty if the directory isn't a crate or cargo fails, since it is only used to describe the changes and to
+/// find the files the build scripts depend on.
+pub fn workspace(crate_dir: &Path) -> Workspace {
+    if !crate_dir.join("Cargo.toml").is_file() {
+        return Workspace::default();
+    }
+
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(crate_dir)
+        .output();
+    let metadata: Metadata = match output {
+        Ok(output) if output.status.success() => match serde_json::from_slice(&output.stdout) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log::warn!("Failed to parse the output from cargo metadata: {}", e);
+                return Workspace::default();
+            },
+        },
+        Ok(output) => {
+            log::warn!("cargo metadata failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            return Workspace::default();
+        },
+        Err(e) => {
+            log::warn!("Failed to run cargo metadata: {}", e);
+            return Workspace::default();
+        },
+    };
+
+    let packages = metadata
+        .packages
+        .into_iter()
+        .filter_map(|package| {
+            let dir = package.manifest_path.parent()?.to_path_buf();
+            let dependencies = package.dependencies.into_iter().map(|dependency| dependency.name).collect();
+            let has_lib = package.targets.iter().flat_map(|target| target.kind.iter()).any(|kind| {
+                matches!(kind.as_str(), "lib" | "rlib" | "dylib" | "proc-macro")
+            });
+            Some(Package {
+                name: package.name,
+                dir,
+                dependencies,
+                has_lib,
+            })
+        })
+        .collect();
+    Workspace {
+        packages,
+        target_dir: Some(metadata.target_directory),
+    }
+}
+
+/// Find the package that a path belongs to, picking the innermost one if the packages are nested
+pub fn find_package<'a>(packages: &'a [Package], path: &Path) -> Option<&'a Package> {
+    packages
+        .iter()
+        .filter(|package| path.starts_with(&package.dir))
+        .max_by_key(|package| package.dir.components().count())
+}
+
+/// Find the packages that have to be built again after the changed ones, which are those and all the packages in the
+/// workspace that depend on them, directly or through each other
+pub fn affected_packages(packages: &[Package], changed: &[String]) -> BTreeSet<String> {
+    let mut affected: BTreeSet<String> = changed.iter().cloned().collect();
+    loop {
+        let dependents: Vec<String> = packages
+            .iter()
+            .filter(|package| !affected.contains(&package.name))
+            .filter(|package| package.dependencies.iter().any(|dependency| affected.contains(dependency)))
+            .map(|package| package.name.clone())
+            .collect();
+        if dependents.is_empty() {
+            return affected;
+        }
+        affected.extend(dependents);
+    }
+}
+
+/// Find the paths the build scripts of the packages told cargo to rerun them for with rerun-if-changed
+///
+/// These are read from the output the build scripts left in the target directory, for every profile and target that
+/// has been built. Paths that no longer exist or are inside the target directory are left out.
+pub fn build_inputs(target_dir: &Path, packages: &[Package]) -> BTreeSet<PathBuf> {
+    let mut inputs = BTreeSet::new();
+    for build_dir in build_dirs(target_dir) {
+        let entries = match std::fs::read_dir(&build_dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            // The directories are named after the package with a hash of the build configuration after it
+            let name = entry.file_name();
+            let package = name
+                .to_str()
+                .and_then(|name| name.rsplit_once('-'))
+                .and_then(|(name, _)| packages.iter().find(|package| package.name == name));
+            let package = match package {
+                Some(package) => package,
+                None => continue,
+            };
+            let output = match std::fs::read_to_string(entry.path().join("output")) {
+                Ok(output) => output,
+                Err(_) => continue,
+            };
+            inputs.extend(
+                output
+                    .lines()
+                    .filter_map(|line| {
+                        line.strip_prefix("cargo::rerun-if-changed=")
+                            .or_else(|| line.strip_prefix("cargo:rerun-if-changed="))
+                    })
+                    .filter_map(|path| package.dir.join(path).canonicalize().ok())
+                    .filter(|path| !path.starts_with(target_dir)),
+            );
+        }
+    }
+    inputs
+}
+
+/// The build directories in the target directory, for each profile and for each profile of every target triple
+fn build_dirs(target_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let entries = match std::fs::read_dir(target_dir) {
+        Ok(entries) => entries,
+        Err(_) => return dirs,
+    };
+    for entry in entries.flatten().filter(|entry| entry.path().is_dir()) {
+        if entry.path().join("build").is_dir() {
+            dirs.push(entry.path().join("build"));
+        } else if let Ok(profiles) = std::fs::read_dir(entry.path()) {
+            dirs.extend(
+                profiles
+                    .flatten()
+                    .map(|profile| profile.path().join("build"))
+                    .filter(|dir| dir.is_dir()),
+            );
+        }
+    }
+    dirs
+}
+
+/// Find the locks another cargo process holds right now that the commands would have to wait for
+///
+/// Cargo locks `.cargo-lock` in the directory of each profile while it builds, and `.package-cache` in its home while
+/// it downloads or updates the index.
+#[cfg(unix)]
+pub fn held_locks(target_dir: &Path) -> Vec<PathBuf> {
+    let package_cache = cargo_home().map(|home| home.join(".package-cache"));
+    lock_files(target_dir).into_iter().chain(package_cache).filter(|path| is_locked(path)).collect()
+}
+
+#[cfg(not(unix))]
+pub fn held_locks(_target_dir: &Path) -> Vec<PathBuf> {
+    Vec::new()
+}
+
+/// The build locks in the target directory, for each profile and for each profile of every target triple
+#[cfg(unix)]
+fn lock_files(target_dir: &Path) -> Vec<PathBuf> {
+    let mut locks = Vec::new();
+    let entries = match std::fs::read_dir(target_dir) {
+        Ok(entries) => entries,
+        Err(_) => return locks,
+    };
+    for entry in entries.flatten().filter(|entry| entry.path().is_dir()) {
+        if entry.path().join(".cargo-lock").is_file() {
+            locks.push(entry.path().join(".cargo-lock"));
+        } else if let Ok(profiles) = std::fs::read_dir(entry.path()) {
+            locks.extend(
+                profiles
+                    .flatten()
+                    .map(|profile| profile.path().join(".cargo-lock"))
+                    .filter(|lock| lock.is_file()),
+            );
+        }
+    }
+    locks
+}
+
+/// Check if another process holds the lock on the file
+///
+/// Cargo takes an exclusive lock, so a shared one can't be taken while it builds. Ours is released right away when the
+/// file is closed.
+#[cfg(unix)]
+fn is_locked(path: &Path) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) };
+    result != 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EWOULDBLOCK)
+}
+
+/// The directory cargo keeps its caches in
+#[cfg(unix)]
+fn cargo_home() -> Option<PathBuf> {
+    match std::env::var_os("CARGO_HOME") {
+        Some(home) => Some(home.into()),
+        None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")),
+    }
+}
+
+/// Get the target triple of the host from rustc
+pub fn host_triple() -> Option<String> {
+    let output = std::process::Command::new("rustc").arg("-vV").output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(|host| host.trim().into())
+}
+
+/// A line of output from cargo with `--message-format=json`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message {
+    /// A warning or error from the compiler
+    Diagnostic(Diagnostic),
+    /// Whether the build succeeded, which comes before any tests run
+    BuildFinished(bool),
+    /// Something else, like an artifact that was built
+    Other,
+}
+
+/// A warning or error from the compiler or clippy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Like error, warning or note
+    pub level: String,
+    /// Like E0308 or clippy::needless_return
+    pub code: Option<String>,
+    pub message: String,
+    /// Where the diagnostic points to, relative to the workspace, if it points anywhere
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The diagnostic as it is printed without JSON
+    pub rendered: String,
+}
+
+impl Diagnostic {
+    /// Check if the diagnostic points to the code, unlike the ones that sum up the others like `aborting due to`
+    pub fn has_location(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonMessage {
+    reason: String,
+    message: Option<JsonDiagnostic>,
+    success: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct JsonDiagnostic {
+    message: String,
+    level: String,
+    code: Option<JsonCode>,
+    #[serde(default)]
+    spans: Vec<JsonSpan>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct JsonSpan {
+    file_name: PathBuf,
+    line_start: usize,
+    column_start: usize,
+    is_primary: bool,
+}
+
+/// Parse a line of output from cargo as a JSON message, returns None for anything else like the output of the tests
+pub fn parse_message(line: &str) -> Option<Message> {
+    if !line.starts_with("{\"reason\":") {
+        return None;
+    }
+    let message: JsonMessage = serde_json::from_str(line).ok()?;
+    Some(match (message.reason.as_str(), message.message) {
+        ("compiler-message", Some(diagnostic)) => {
+            let span = diagnostic.spans.iter().find(|span| span.is_primary);
+            Message::Diagnostic(Diagnostic {
+                level: diagnostic.level,
+                code: diagnostic.code.map(|code| code.code),
+                message: diagnostic.message,
+                file: span.map(|span| span.file_name.clone()),
+                line: span.map(|span| span.line_start),
+                column: span.map(|span| span.column_start),
+                rendered: diagnostic.rendered.unwrap_or_default(),
+            })
+        },
+        ("build-finished", _) => Message::BuildFinished(message.success.unwrap_or(false)),
+        _ => Message::Other,
+    })
+}
+
+/// Find the nearest directory containing a Cargo.toml, starting at the directory and walking up like cargo does
+pub fn find_manifest_dir(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .map(PathBuf::from)
+}
+
+/// Check that the directory is a crate or a workspace root, with a suggestion for what to do if it isn't
+pub fn check_crate_dir(crate_dir: &Path) -> Result<(), String> {
+    if !crate_dir.is_dir() {
+        return Err(format!("{} is not a directory", crate_dir.to_string_lossy()));
+    }
+    if crate_dir.join("Cargo.toml").is_file() {
+        return Ok(());
+    }
+
+    let mut message = format!("{} does not contain a Cargo.toml", crate_dir.to_string_lossy());
+    match crate_dir.parent().and_then(find_manifest_dir) {
+        Some(parent) => write!(message, ", did you mean {}?", parent.to_string_lossy()).unwrap(),
+        None => message.push_str(", run cargo init to create a crate there."),
+    }
+    message.push_str(" Use --cmd to run commands that don't need cargo.");
+    Err(message)
+}
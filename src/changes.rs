@@ -0,0 +1,510 @@
+use crate::cargo::{self, Package};
+use crate::git::Tracked;
+use crate::hashes::{self, Hashes, Update};
+use crate::ignores::Ignores;
+use crate::pipeline::Pipeline;
+use crate::settings::Debounce;
+use ignore::overrides::Override;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often to check if the runner is done when there are changes waiting for it
+pub const RUNNING_POLL: Duration = Duration::from_millis(100);
+
+/// Changes this soon after git touched its own files are assumed to be made by git, like a checkout or a rebase
+const GIT_BURST: Duration = Duration::from_secs(1);
+
+/// What started a run, which the commands get in AUTO_CHECK_TRIGGER
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// The first run after starting
+    Initial,
+    /// Files changed, including another branch being checked out
+    Change,
+    /// Asked for from the terminal or with the trigger file
+    Manual,
+    /// Scheduled commands that were due
+    Schedule,
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Trigger::Initial => write!(f, "initial"),
+            Trigger::Change => write!(f, "change"),
+            Trigger::Manual => write!(f, "manual"),
+            Trigger::Schedule => write!(f, "schedule"),
+        }
+    }
+}
+
+pub enum Action {
+    Nothing,
+    /// The new commands to run after the config file was reloaded
+    Reload(Box<Pipeline>),
+    /// A run for some other reason than the files that changed, with the reason to log
+    Custom(Trigger, String),
+    /// Files that changed
+    FilesChanged(ChangeSet),
+    /// The same as FilesChanged, but the files were changed by git
+    GitOperation(ChangeSet),
+    /// Nothing but the lock files changed, like after cargo update
+    LockfileChanged(Vec<PathBuf>),
+    /// The names of the scheduled commands that are due
+    Scheduled(Vec<String>),
+}
+
+/// The files that changed since the last run
+#[derive(Debug, Default)]
+pub struct ChangeSet {
+    pub paths: Vec<PathBuf>,
+    /// The names of the packages in the workspace the paths belong to
+    pub packages: Vec<String>,
+    /// The packages to build for the changes together with the ones that depend on them, if not the whole workspace
+    pub affected: Option<Vec<String>>,
+    /// Nothing but the doc comments changed in the Rust files of packages with a library, and nothing else changed
+    pub docs_only: bool,
+}
+
+impl Action {
+    /// The paths that changed, if the action is for changed files
+    pub fn paths(&self) -> Option<&[PathBuf]> {
+        match self {
+            Action::FilesChanged(changes) | Action::GitOperation(changes) => Some(&changes.paths),
+            Action::LockfileChanged(paths) => Some(paths),
+            _ => None,
+        }
+    }
+
+    /// The names of the packages in the workspace that changed, if the action is for changed files
+    pub fn packages(&self) -> &[String] {
+        match self {
+            Action::FilesChanged(changes) | Action::GitOperation(changes) => &changes.packages,
+            _ => &[],
+        }
+    }
+
+    /// The packages the changed files belong to together with the ones that depend on them, or None if the changes can
+    /// affect the whole workspace
+    pub fn affected(&self) -> Option<&[String]> {
+        match self {
+            Action::FilesChanged(changes) | Action::GitOperation(changes) => changes.affected.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// What started the run for the action
+    pub fn trigger(&self) -> Trigger {
+        match self {
+            Action::Custom(trigger, _) => *trigger,
+            Action::Scheduled(_) => Trigger::Schedule,
+            _ => Trigger::Change,
+        }
+    }
+
+    /// Check if nothing but doc comments changed
+    pub fn docs_only(&self) -> bool {
+        matches!(self, Action::FilesChanged(changes) | Action::GitOperation(changes) if changes.docs_only)
+    }
+
+    /// Check if any of the changed files are lock files, manifests or the config of cargo deny, which can change what
+    /// the commands for the dependencies find
+    pub fn changes_dependencies(&self) -> bool {
+        self.paths().is_some_and(|paths| paths.iter().any(|path| is_lockfile(path) || is_dependency_file(path)))
+    }
+}
+
+/// Paths in the crate directory that affect every package in the workspace
+const WORKSPACE_FILES: &[&str] = &["Cargo.toml", ".cargo", "rust-toolchain", "rust-toolchain.toml"];
+
+/// Check if a path is the lock file of a workspace
+pub fn is_lockfile(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "Cargo.lock")
+}
+
+/// Files besides the lock file that can change what the commands for the dependencies find
+const DEPENDENCY_FILES: &[&str] = &["Cargo.toml", "deny.toml"];
+
+/// Check if a path is a manifest of a package or a workspace, or the config of cargo deny
+fn is_dependency_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| DEPENDENCY_FILES.iter().any(|file| name == *file))
+}
+
+pub struct Changes {
+    base_dir: PathBuf,
+    pub ignores: Ignores,
+    /// Paths outside the base directory that are also watched, these are recorded with the full path
+    pub watch: Vec<PathBuf>,
+    /// Only changes to files with these extensions are recorded, or any file if empty
+    pub ext: Vec<String>,
+    /// Only changes to paths in the base directory matching these globs are recorded, or any path if empty
+    pub includes: Override,
+    /// Changes to files larger than this are ignored
+    pub max_file_size: Option<u64>,
+    /// Only changes to the files tracked by git in the base directory are recorded, if set
+    pub tracked: Option<Tracked>,
+    /// The packages in the workspace, used to tell which package a change belongs to
+    pub packages: Vec<Package>,
+    /// The paths the build scripts depend on, changes to them are always recorded
+    pub build_inputs: BTreeSet<PathBuf>,
+    /// The hashes of the files, to skip writes that didn't change the contents
+    pub hashes: Hashes,
+    /// Set while the runner is busy, the changes are kept until it is done
+    pub running: Arc<AtomicBool>,
+    /// Set to stop the current run when something changes, if the runner can be cancelled
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Set while there are changes waiting for the next run, so the runner can leave out the slow commands
+    pub pending: Arc<AtomicBool>,
+    custom: Option<(Trigger, String)>,
+    changed: BTreeSet<PathBuf>,
+    /// Set when any of the current changes is to more than the doc comments of a Rust file
+    code_changed: bool,
+    /// The changes while a formatting command ran, which are only recorded if it didn't make them
+    held: Vec<(PathBuf, Update)>,
+    /// When the first and the last of the current changes were recorded
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+    /// When the runner was last told to run
+    last_run: Option<Instant>,
+    /// When git last touched its own files, and if the current changes look like they were made by git
+    last_git: Option<Instant>,
+    git_operation: bool,
+}
+
+impl Changes {
+    pub fn new<P: Into<PathBuf>>(base_dir: P, ignores: Ignores) -> Changes {
+        let base_dir = base_dir.into();
+        assert!(base_dir.is_absolute());
+        Changes {
+            base_dir,
+            ignores,
+            watch: Vec::new(),
+            ext: Vec::new(),
+            includes: Override::empty(),
+            tracked: None,
+            max_file_size: None,
+            packages: Vec::new(),
+            build_inputs: BTreeSet::new(),
+            hashes: Hashes::default(),
+            running: Default::default(),
+            cancel: None,
+            pending: Default::default(),
+            custom: None,
+            changed: Default::default(),
+            code_changed: false,
+            held: Vec::new(),
+            first_change: None,
+            last_change: None,
+            last_run: None,
+            last_git: None,
+            git_operation: false,
+        }
+    }
+
+    pub fn add_custom<T: Into<String>>(&mut self, trigger: Trigger, reason: T) {
+        let reason = reason.into();
+        if let Some((_, queued)) = &self.custom {
+            log::debug!("{} while a run is already queued ({}), running once for both", reason, queued);
+        }
+        self.custom = Some((trigger, reason));
+        self.pending.store(true, Ordering::Relaxed);
+        let now = Instant::now();
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+    }
+
+    /// Record that git changed its own files, which means the working tree may be changing too
+    pub fn add_git_activity(&mut self) {
+        self.last_git = Some(Instant::now());
+        if self.first_change.is_some() {
+            self.git_operation = true;
+        }
+    }
+
+    pub fn add<P: AsRef<Path>>(&mut self, fpath: &P) {
+        let fpath = fpath.as_ref();
+        let ignored = self.ignores.is_ignored(fpath, false);
+        match fpath.strip_prefix(&self.base_dir) {
+            // The build scripts depend on these even if they are ignored or don't match the filters
+            _ if self.is_build_input(fpath) => self.record(fpath.strip_prefix(&self.base_dir).unwrap_or(fpath)),
+            Ok(fpath) if ignored => {
+                log::trace!("Ignoring path from the ignore files: {}", fpath.to_string_lossy());
+            },
+            Ok(fpath) if !self.includes.is_empty() && !self.includes.matched(fpath, false).is_whitelist() => {
+                log::trace!("Ignoring path that isn't included: {}", fpath.to_string_lossy());
+            },
+            Ok(path) if self.tracked.as_ref().is_some_and(|tracked| !tracked.contains(fpath)) => {
+                log::trace!("Ignoring path that isn't tracked by git: {}", path.to_string_lossy());
+            },
+            Ok(fpath) => self.insert(fpath),
+            Err(_) if self.is_watched(fpath) => self.insert(fpath),
+            Err(_) => {
+                log::error!("Ignoring unknown path: {}", fpath.to_string_lossy());
+            },
+        }
+    }
+
+    /// Record that a path was renamed, moving the changes already recorded below it to the new path
+    ///
+    /// Returns true if the path itself was already recorded as changed, like a temporary file that was moved into
+    /// place, so the change is only recorded for the new path.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> bool {
+        self.hashes.rename(from, to);
+
+        let (from, to) = match (from.strip_prefix(&self.base_dir), to.strip_prefix(&self.base_dir)) {
+            (Ok(from), Ok(to)) => (from, to),
+            _ => return false,
+        };
+        let pending = self.changed.contains(from);
+        let moved: Vec<PathBuf> = self.changed.iter().filter(|path| path.starts_with(from)).cloned().collect();
+        for path in moved {
+            self.changed.remove(&path);
+            if let Ok(rest) = path.strip_prefix(from) {
+                self.changed.insert(hashes::moved_path(to, rest));
+                self.code_changed = true;
+            }
+        }
+        pending
+    }
+
+    /// Check if a path is inside one of the extra watched paths outside the base directory
+    pub fn is_watched(&self, fpath: &Path) -> bool {
+        self.watch.iter().any(|path| fpath.starts_with(path))
+    }
+
+    /// Check if a path is one of the paths the build scripts depend on, or inside one of them
+    pub fn is_build_input(&self, fpath: &Path) -> bool {
+        self.build_inputs.iter().any(|input| fpath.starts_with(input))
+    }
+
+    fn has_ext(&self, fpath: &Path) -> bool {
+        self.ext.is_empty()
+            || fpath
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| self.ext.iter().any(|wanted| wanted == ext))
+    }
+
+    /// Check if a file is larger than the maximum size, a file that was removed is never too large
+    fn is_too_large(&self, fpath: &Path) -> bool {
+        match (self.max_file_size, std::fs::metadata(self.base_dir.join(fpath))) {
+            (Some(max_file_size), Ok(metadata)) => metadata.is_file() && metadata.len() > max_file_size,
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, fpath: &Path) {
+        if !self.has_ext(fpath) {
+            log::trace!("Ignoring path without a matching extension: {}", fpath.to_string_lossy());
+        } else if self.is_too_large(fpath) {
+            log::debug!("Ignoring file larger than the maximum size: {}", fpath.to_string_lossy());
+        } else {
+            self.record(fpath);
+        }
+    }
+
+    /// Record a change that passed the filters, unless the contents are the same as before or a formatter wrote them
+    fn record(&mut self, fpath: &Path) {
+        let path = self.base_dir.join(fpath);
+        let update = self.hashes.update(&path);
+        if update == Update::Unchanged {
+            log::debug!("Ignoring write that didn't change the contents: {}", fpath.to_string_lossy());
+            return;
+        }
+        if hashes::is_writing() {
+            // Wait for the formatter to finish, but keep checking until it does
+            log::trace!("Holding change until the formatter is done: {}", fpath.to_string_lossy());
+            self.held.push((fpath.into(), update));
+            self.first_change.get_or_insert(Instant::now());
+            return;
+        }
+        if self.hashes.contents(&path).is_some_and(|hash| hashes::take_written(&path, hash)) {
+            log::debug!("Ignoring write by the formatter: {}", fpath.to_string_lossy());
+            return;
+        }
+        self.mark(fpath, update);
+    }
+
+    /// Record the changes that were held while a formatter ran, leaving out the ones it made
+    fn release_held(&mut self) {
+        if hashes::is_writing() {
+            return;
+        }
+        for (fpath, update) in std::mem::take(&mut self.held) {
+            let path = self.base_dir.join(&fpath);
+            if self.hashes.contents(&path).is_some_and(|hash| hashes::take_written(&path, hash)) {
+                log::debug!("Ignoring write by the formatter: {}", fpath.to_string_lossy());
+            } else {
+                self.mark(&fpath, update);
+            }
+        }
+    }
+
+    /// Record a change that is going to trigger a run
+    fn mark(&mut self, fpath: &Path, update: Update) {
+        if update == Update::Changed {
+            self.code_changed = true;
+        }
+        if self.running.load(Ordering::Relaxed) {
+            match &self.cancel {
+                Some(cancel) if !cancel.swap(true, Ordering::Relaxed) => {
+                    log::info!("Restarting after a change to {}", fpath.to_string_lossy());
+                },
+                _ => log::debug!("Queued change until the current run is done: {}", fpath.to_string_lossy()),
+            }
+        } else {
+            log::debug!("Detected change: {}", fpath.to_string_lossy());
+        }
+        self.changed.insert(fpath.into());
+        self.pending.store(true, Ordering::Relaxed);
+        let now = Instant::now();
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+        if self.last_git.is_some_and(|last_git| now.duration_since(last_git) < GIT_BURST) {
+            self.git_operation = true;
+        }
+    }
+
+    /// Hash the files in the base directory, so writes that don't change them can be skipped
+    pub fn scan(&mut self) {
+        self.hashes.scan(&self.base_dir, &self.ignores);
+    }
+
+    /// Record the files that changed without an event, after events were lost, returns how many there were
+    pub fn resync(&mut self) -> usize {
+        let changed = self.hashes.changed(&self.base_dir, &self.ignores);
+        for path in changed.iter() {
+            self.add(path);
+        }
+        changed.len()
+    }
+
+    /// Check if a path inside the base directory is ignored
+    pub fn is_ignored(&self, fpath: &Path, is_dir: bool) -> bool {
+        self.ignores.is_ignored(fpath, is_dir)
+    }
+
+    /// When the current changes should be run, if there are any
+    pub fn deadline(
+        &self,
+        delay: Duration,
+        debounce: Debounce,
+        max_wait: Option<Duration>,
+        min_interval: Option<Duration>,
+    ) -> Option<Instant> {
+        let first = self.first_change?;
+        // Nothing new is started until the current run is done, so keep checking until it is
+        if self.running.load(Ordering::Relaxed) || hashes::is_writing() {
+            return Some(Instant::now() + RUNNING_POLL);
+        }
+        // Anything asked for is run right away
+        if matches!(self.custom, Some((Trigger::Manual, _))) {
+            return Some(first);
+        }
+        // Something that keeps changing the files, like a generator, shouldn't keep the commands running back to back
+        let earliest = match (min_interval, self.last_run) {
+            (Some(min_interval), Some(last_run)) => first.max(last_run + min_interval),
+            _ => first,
+        };
+        if self.custom.is_some() {
+            return Some(earliest);
+        }
+        let deadline = match (debounce, self.last_run) {
+            (Debounce::Fixed, _) => first + delay,
+            (Debounce::Leading, Some(last_run)) => first.max(last_run + delay),
+            (Debounce::Leading, None) => first,
+            (Debounce::Quiet, _) => self.last_change.unwrap_or(first) + delay,
+        };
+        // Git may touch a lot of files over some time, so wait until it has been done for the delay
+        let deadline = match (self.git_operation, self.last_git) {
+            (true, Some(last_git)) => deadline.max(last_git + delay),
+            _ => deadline,
+        };
+        let deadline = match max_wait {
+            Some(max_wait) => deadline.min(first + max_wait),
+            None => deadline,
+        };
+        Some(deadline.max(earliest))
+    }
+
+    pub fn take_current_action(&mut self) -> Action {
+        self.release_held();
+        if !self.held.is_empty() {
+            // The formatter is still running
+            return Action::Nothing;
+        }
+        self.first_change = None;
+        self.last_change = None;
+        let git_operation = std::mem::take(&mut self.git_operation);
+        let code_changed = std::mem::take(&mut self.code_changed);
+        self.pending.store(false, Ordering::Relaxed);
+        if let Some(cancel) = &self.cancel {
+            cancel.store(false, Ordering::Relaxed);
+        }
+
+        if let Some((trigger, reason)) = self.custom.take() {
+            // Return the custom reason for running
+            self.last_run = Some(Instant::now());
+            self.changed = BTreeSet::new(); // Ignore any changes up until now
+            self.running.store(true, Ordering::Relaxed);
+            Action::Custom(trigger, reason)
+        } else if !self.changed.is_empty() {
+            self.last_run = Some(Instant::now());
+            // Return the list of changed files
+            let mut changed = BTreeSet::new();
+            std::mem::swap(&mut changed, &mut self.changed);
+            self.running.store(true, Ordering::Relaxed);
+            let packages: BTreeSet<String> = changed
+                .iter()
+                .filter_map(|fpath| cargo::find_package(&self.packages, &self.base_dir.join(fpath)))
+                .map(|package| package.name.clone())
+                .collect();
+            let changed: Vec<PathBuf> = changed.into_iter().collect();
+            let packages = packages.into_iter().collect();
+            if changed.iter().all(|path| is_lockfile(path)) {
+                return Action::LockfileChanged(changed);
+            }
+            let changes = ChangeSet {
+                affected: self.affected(&changed),
+                docs_only: !code_changed && self.in_libraries(&changed),
+                paths: changed,
+                packages,
+            };
+            if git_operation {
+                Action::GitOperation(changes)
+            } else {
+                Action::FilesChanged(changes)
+            }
+        } else {
+            // There is nothing to do here
+            Action::Nothing
+        }
+    }
+
+    /// Check if all the paths belong to packages that have a library, which can have doc tests
+    fn in_libraries(&self, changed: &[PathBuf]) -> bool {
+        changed.iter().all(|fpath| {
+            cargo::find_package(&self.packages, &self.base_dir.join(fpath)).is_some_and(|package| package.has_lib)
+        })
+    }
+
+    /// The packages to build for the changed paths together with the packages that depend on them, or None if a
+    /// change is outside the packages or to the files of the workspace itself, or if every package is affected
+    fn affected(&self, changed: &[PathBuf]) -> Option<Vec<String>> {
+        let mut names = Vec::new();
+        for fpath in changed {
+            if is_lockfile(fpath) || WORKSPACE_FILES.iter().any(|file| fpath.starts_with(file)) {
+                return None;
+            }
+            names.push(cargo::find_package(&self.packages, &self.base_dir.join(fpath))?.name.clone());
+        }
+        let affected = cargo::affected_packages(&self.packages, &names);
+        if affected.len() == self.packages.len() {
+            return None;
+        }
+        Some(affected.into_iter().collect())
+    }
+}
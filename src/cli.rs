@@ -0,0 +1,415 @@
+use crate::settings::{Backend, Color, Debounce, Preset, Size};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
+use std::path::PathBuf;
+
+const AFTER_HELP: &str = "\
+The options can also be set in .auto-check.toml or auto-check.toml in the crate
+directory. Options given on the command line override the ones in the file.
+The file is reloaded automatically when it changes.
+
+Every option can also be set with an environment variable named after it, like
+AUTO_CHECK_DELAY=500 or AUTO_CHECK_NO_CLIPPY=1. These override the config file,
+but not the command line. AUTO_CHECK_CMD and AUTO_CHECK_CUSTOM_CMD take one
+command per line, and AUTO_CHECK_ONLY and AUTO_CHECK_SKIP a comma separated list.
+
+Every command has a name used by --only and --skip. The built-in commands are
+named check, build, clippy, test and doc, and the other commands are named after
+the cargo subcommand or program they run, or the name given in the config file.";
+
+/// Automatically build, check and test code when it changes
+#[derive(Debug, Parser)]
+#[command(
+    name = "auto-check-rs",
+    version,
+    after_help = AFTER_HELP,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<SubCommand>,
+
+    /// Increase the verbosity level, default is only errors
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only print a line for each command that passes, and the output of the ones that fail
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// When to use colors in the log and in the output of the commands [default: auto]
+    #[arg(long, value_name = "WHEN")]
+    pub color: Option<Color>,
+
+    /// Print the settings after merging the command line, environment and config file, and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Delay in milliseconds before triggering [default: 1000]
+    #[arg(long, value_name = "MS", help_heading = "Watching")]
+    pub delay: Option<u64>,
+
+    /// When to run after a change, fixed waits for the delay after the first change, leading runs right away and
+    /// collects the followups for the delay, and quiet waits until nothing has changed for the delay [default: fixed]
+    #[arg(long, value_enum, value_name = "MODE", help_heading = "Watching")]
+    pub debounce: Option<Debounce>,
+
+    /// The longest time in milliseconds to wait after the first change, even if things keep changing
+    #[arg(long, value_name = "MS", help_heading = "Watching")]
+    pub max_wait: Option<u64>,
+
+    /// The shortest time in milliseconds from the start of one run to the start of the next, so files that keep
+    /// changing don't run the commands back to back. Runs asked for from the terminal or the trigger file don't wait
+    #[arg(long, value_name = "MS", help_heading = "Watching")]
+    pub min_interval: Option<u64>,
+
+    /// Poll the files for changes instead of using the native file system events, for network file systems, Docker
+    /// bind mounts and WSL2 where the events get lost. The interval is in milliseconds
+    #[arg(
+        long,
+        value_name = "MS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1000",
+        help_heading = "Watching"
+    )]
+    pub poll: Option<u64>,
+
+    /// Force a backend for watching the file system instead of the native one for the platform
+    #[arg(long, value_enum, value_name = "BACKEND", help_heading = "Watching")]
+    pub backend: Option<Backend>,
+
+    /// Watch the directories that symbolic links in the crate point to, like vendored or generated code. Links are not
+    /// followed by default
+    #[arg(long, help_heading = "Watching")]
+    pub follow_symlinks: bool,
+
+    /// Also watch this file or directory outside the crate directory, like a shared schema directory the build
+    /// depends on. Can be given multiple times
+    #[arg(long, value_name = "PATH", help_heading = "Watching")]
+    pub watch: Vec<PathBuf>,
+
+    /// Never trigger on changes to paths matching this pattern in .gitignore format, in addition to the ignore files
+    /// and the ignore list in the config file. Can be given multiple times
+    #[arg(long, value_name = "GLOB", help_heading = "Watching")]
+    pub exclude: Vec<String>,
+
+    /// Only trigger on changes to paths in the crate directory matching this glob, like 'src/**', and ignore everything
+    /// else. Can be given multiple times
+    #[arg(long, value_name = "GLOB", help_heading = "Watching")]
+    pub include: Vec<String>,
+
+    /// Changes to paths matching this glob, like '*.md' or 'LICENSE*', can't affect the build or the tests, and don't
+    /// run anything if nothing else changed. Can be given multiple times
+    #[arg(long, value_name = "GLOB", help_heading = "Watching")]
+    pub no_op: Vec<String>,
+
+    /// Only trigger on changes to files with these extensions, can be comma separated or given multiple times
+    #[arg(long, value_name = "EXT", value_delimiter = ',', help_heading = "Watching")]
+    pub ext: Vec<String>,
+
+    /// Only trigger on changes to files that are tracked by git, the list is read again when the git index changes
+    #[arg(long, help_heading = "Watching")]
+    pub tracked_only: bool,
+
+    /// Ignore changes to files larger than this, like 10M or 512K. The default is 10M, and 0 turns it off
+    #[arg(long, value_name = "SIZE", help_heading = "Watching")]
+    pub max_file_size: Option<Size>,
+
+    /// Start a run right away when this file is created or touched, even if it is ignored. The default is
+    /// .auto-check-trigger in the crate directory
+    #[arg(long, value_name = "PATH", help_heading = "Watching")]
+    pub trigger_file: Option<PathBuf>,
+
+    /// Don't always run once after startup, wait for a change
+    #[arg(long, help_heading = "Watching")]
+    pub no_run_first: bool,
+
+    /// Use the defaults for the built-in commands from a preset, the other options still override it
+    #[arg(long, value_enum, help_heading = "Pipeline")]
+    pub preset: Option<Preset>,
+
+    /// Run the commands once and exit with the status of the run instead of watching
+    #[arg(long, help_heading = "Watching")]
+    pub once: bool,
+
+    /// Stop the running commands and start over when something changes, instead of waiting for them to finish
+    #[arg(long, conflicts_with = "once", help_heading = "Watching")]
+    pub restart_on_change: bool,
+
+    /// Use the named profile from the config file
+    #[arg(short, long, value_name = "NAME", help_heading = "Pipeline")]
+    pub profile: Option<String>,
+
+    /// Run this command instead of the built-in check, build, clippy, test and doc commands, can be given multiple
+    /// times to run several commands in order
+    #[arg(long, value_name = "CMD", help_heading = "Pipeline")]
+    pub cmd: Vec<String>,
+
+    /// Run the specified command after the other checks, arguments are split and quoted like in a shell. Can be
+    /// given multiple times to run several commands in order
+    #[arg(short, long, value_name = "CMD", help_heading = "Pipeline")]
+    pub custom_cmd: Vec<String>,
+
+    /// Run the commands given as a line through the shell, so they can use pipes, && and environment variables
+    #[arg(long, help_heading = "Pipeline")]
+    pub shell: bool,
+
+    /// Kill a command and mark it as failed if it runs for longer than this many seconds, 0 turns it off
+    #[arg(long, value_name = "SECS", help_heading = "Pipeline")]
+    pub timeout: Option<u64>,
+
+    /// Limit the memory each command and the processes it starts can use together, like 4G, 0 turns it off
+    #[arg(long, value_name = "SIZE", help_heading = "Pipeline")]
+    pub memory_limit: Option<Size>,
+
+    /// Limit the number of CPUs each command and the processes it starts can use together, like 1.5, 0 turns it off
+    #[arg(long, value_name = "CPUS", help_heading = "Pipeline")]
+    pub cpu_limit: Option<f64>,
+
+    /// Run the commands with this niceness from 1 to 19 and idle I/O priority where supported, 0 turns it off
+    #[arg(long, value_name = "LEVEL", value_parser = clap::value_parser!(u8).range(0..=19), help_heading = "Pipeline")]
+    pub nice: Option<u8>,
+
+    /// Run the commands in a terminal of their own, so they print colors and progress bars even when piped
+    #[arg(long, help_heading = "Pipeline")]
+    pub pty: bool,
+
+    /// Run the commands on another machine over SSH, like user@host or a host from the SSH config. The hooks still run
+    /// here
+    #[arg(long, value_name = "HOST", help_heading = "Remote")]
+    pub ssh: Option<String>,
+
+    /// The directory of the crate on the remote machine, relative to the home directory there unless it is absolute
+    /// [default: the same path as here]
+    #[arg(long, value_name = "DIR", help_heading = "Remote")]
+    pub remote_dir: Option<String>,
+
+    /// Copy the crate directory to the remote machine with rsync before every run, instead of relying on a shared
+    /// checkout
+    #[arg(long, help_heading = "Remote")]
+    pub sync: bool,
+
+    /// Run the commands in a container of this image, with the crate directory mounted at the same path. The hooks
+    /// still run here
+    #[arg(long, value_name = "IMAGE", help_heading = "Remote")]
+    pub container: Option<String>,
+
+    /// The program that runs the containers [default: podman if it is installed, docker otherwise]
+    #[arg(long, value_name = "PROGRAM", help_heading = "Remote")]
+    pub container_engine: Option<String>,
+
+    /// Other arguments for the container engine, like volumes for the caches of cargo
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true, help_heading = "Remote")]
+    pub container_args: Option<String>,
+
+    /// Print every line of output after the name of the command, and the stream for stderr, like [test:err]
+    #[arg(long, help_heading = "Pipeline")]
+    pub prefix: bool,
+
+    /// Build in target/auto-check, so the commands don't wait for the lock on the target directory of the editor
+    #[arg(long, help_heading = "Cargo")]
+    pub isolated_target_dir: bool,
+
+    /// Fail the cargo commands when there are warnings, by adding -Dwarnings to RUSTFLAGS
+    #[arg(long, help_heading = "Cargo")]
+    pub deny_warnings: bool,
+
+    /// Wait for other cargo processes, like rust-analyzer, to release their locks before running the commands
+    #[arg(long, help_heading = "Cargo")]
+    pub wait_for_lock: bool,
+
+    /// Run the rest of the commands when one fails, and report all the failures at the end
+    #[arg(long, help_heading = "Pipeline")]
+    pub keep_going: bool,
+
+    /// Only run the built-in clippy and test commands after check passed, and if nothing changed in the meantime
+    #[arg(long, help_heading = "Pipeline")]
+    pub tiered: bool,
+
+    /// After a failed run, only run the commands that failed until they pass, then run all of them again
+    #[arg(long, visible_alias = "only-failures", help_heading = "Pipeline")]
+    pub failed_first: bool,
+
+    /// In a workspace, only build and test the packages the changed files belong to and the ones that depend on them,
+    /// by adding -p to the cargo commands
+    #[arg(long, help_heading = "Pipeline")]
+    pub affected_only: bool,
+
+    /// When only integration tests in tests/ or test modules like src/foo/tests.rs changed, only run those tests
+    #[arg(long, help_heading = "Pipeline")]
+    pub targeted_tests: bool,
+
+    /// When nothing but doc comments changed, only run the doc tests of the changed crates with cargo test --doc
+    #[arg(long, help_heading = "Pipeline")]
+    pub doc_tests: bool,
+
+    /// With --targeted-tests or --doc-tests, run all the tests after this many targeted runs in a row, or never with 0
+    /// [default: 10]
+    #[arg(long, value_name = "RUNS", help_heading = "Pipeline")]
+    pub full_test_every: Option<u32>,
+
+    /// Only run the commands with this name, can be given multiple times
+    #[arg(long, value_name = "NAME", help_heading = "Pipeline")]
+    pub only: Vec<String>,
+
+    /// Don't run the commands with this name, can be given multiple times
+    #[arg(long, value_name = "NAME", help_heading = "Pipeline")]
+    pub skip: Vec<String>,
+
+    /// Features to enable for check, build, clippy, test and doc, can be comma separated or given multiple times
+    #[arg(short = 'F', long, value_name = "FEATURES", value_delimiter = ',', help_heading = "Cargo")]
+    pub features: Vec<String>,
+
+    /// Enable all features for check, build, clippy, test and doc
+    #[arg(long, help_heading = "Cargo")]
+    pub all_features: bool,
+
+    /// Disable the default features for check, build, clippy, test and doc
+    #[arg(long, help_heading = "Cargo")]
+    pub no_default_features: bool,
+
+    /// Build in release mode for all the cargo commands
+    #[arg(long, conflicts_with = "cargo_profile", help_heading = "Cargo")]
+    pub release: bool,
+
+    /// Build with the named cargo profile for all the cargo commands
+    #[arg(long, value_name = "NAME", help_heading = "Cargo")]
+    pub cargo_profile: Option<String>,
+
+    /// Build for the target triple in all the cargo commands
+    #[arg(long, value_name = "TRIPLE", help_heading = "Cargo")]
+    pub target: Option<String>,
+
+    /// Run the tests using cross when the target can't run on this machine
+    #[arg(long, requires = "target", help_heading = "Cargo")]
+    pub cross: bool,
+
+    /// Limit the number of parallel jobs for every cargo command, to leave room for the editor
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), help_heading = "Cargo")]
+    pub jobs: Option<u32>,
+
+    /// Run the built-in cargo commands with this toolchain, like cargo +nightly
+    #[arg(long, value_name = "TOOLCHAIN", help_heading = "Cargo")]
+    pub toolchain: Option<String>,
+
+    /// Run cargo fmt --all -- --check before cargo check, which the strict and ci presets do already
+    #[arg(long, conflicts_with = "no_fmt", help_heading = "Pipeline")]
+    pub fmt: bool,
+
+    /// Don't run cargo fmt, even with the strict and ci presets
+    #[arg(long, help_heading = "Pipeline")]
+    pub no_fmt: bool,
+
+    /// Format the code with cargo fmt --all instead of only checking it, without the writes starting another run
+    #[arg(long, conflicts_with = "no_fmt", help_heading = "Pipeline")]
+    pub fix_fmt: bool,
+
+    /// Don't run cargo check
+    #[arg(long, help_heading = "Pipeline")]
+    pub no_check: bool,
+
+    /// Extra arguments for cargo check, split like in a shell
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true, help_heading = "Pipeline")]
+    pub check_args: Option<String>,
+
+    /// Run cargo build after cargo check, for errors that only show up when generating code or linking, or to have a
+    /// fresh binary for the commands after it
+    #[arg(long, help_heading = "Pipeline")]
+    pub build: bool,
+
+    /// Extra arguments for cargo build, split like in a shell
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true, help_heading = "Pipeline")]
+    pub build_args: Option<String>,
+
+    /// Don't run cargo clippy
+    #[arg(long, help_heading = "Pipeline")]
+    pub no_clippy: bool,
+
+    /// Extra arguments for cargo clippy, split like in a shell
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true, help_heading = "Pipeline")]
+    pub clippy_args: Option<String>,
+
+    /// Don't run cargo test
+    #[arg(long, help_heading = "Pipeline")]
+    pub no_test: bool,
+
+    /// Extra arguments for cargo test, split like in a shell
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true, help_heading = "Pipeline")]
+    pub test_args: Option<String>,
+
+    /// Run the tests with cargo nextest run, which is the default when it is installed
+    #[arg(long, conflicts_with = "no_nextest", help_heading = "Pipeline")]
+    pub nextest: bool,
+
+    /// Run the tests with cargo test, even when cargo nextest is installed
+    #[arg(long, help_heading = "Pipeline")]
+    pub no_nextest: bool,
+
+    /// Run cargo audit when Cargo.lock or a Cargo.toml changes, to find dependencies with known vulnerabilities
+    #[arg(long, help_heading = "Pipeline")]
+    pub audit: bool,
+
+    /// Run cargo deny check when Cargo.lock, a Cargo.toml or deny.toml changes, to check the licenses, bans, advisories
+    /// and sources of the dependencies
+    #[arg(long, help_heading = "Pipeline")]
+    pub deny: bool,
+
+    /// Run cargo doc --no-deps after the tests, to catch broken doc links and other rustdoc errors
+    #[arg(long, help_heading = "Pipeline")]
+    pub doc: bool,
+
+    /// Extra arguments for cargo doc, split like in a shell
+    #[arg(long, value_name = "ARGS", allow_hyphen_values = true, help_heading = "Pipeline")]
+    pub doc_args: Option<String>,
+
+    /// The directories of the crates to watch, each running its own commands when its files change. Defaults to the
+    /// nearest directory with a Cargo.toml, starting at the current directory
+    #[arg(value_name = "CRATE_DIR")]
+    pub crate_dirs: Vec<PathBuf>,
+
+    /// Watch the crate with this Cargo.toml instead of a crate directory, the same as for cargo
+    #[arg(long, value_name = "PATH", conflicts_with = "crate_dirs")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// Arguments for the test binaries, appended to the test command after a --
+    #[arg(last = true, value_name = "TEST_ARGS")]
+    pub trailing_args: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubCommand {
+    /// Print a completion script for the shell to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Write a starter config file with the default commands to the crate directory
+    Init {
+        /// Overwrite the config file if it already exists
+        #[arg(long)]
+        force: bool,
+
+        /// The directory of the crate
+        #[arg(default_value = ".")]
+        crate_dir: PathBuf,
+    },
+}
+
+impl SubCommand {
+    /// Run the subcommand instead of watching the crate directory
+    pub fn run(self) {
+        match self {
+            SubCommand::Completions { shell } => {
+                clap_complete::generate(shell, &mut Args::command(), "auto-check-rs", &mut std::io::stdout());
+            },
+            SubCommand::Init { force, crate_dir } => match crate::init::run(&crate_dir, force) {
+                Ok(path) => println!("Wrote {}", path.to_string_lossy()),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                },
+            },
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use crate::changes::Trigger;
+use crate::remote::{Container, Ssh};
+use crate::settings::{Interval, Size};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The shell and its flag for running a line, for the commands that are run through a shell
+#[cfg(unix)]
+const SHELL: &[&str] = &["sh", "-c"];
+#[cfg(not(unix))]
+const SHELL: &[&str] = &["cmd", "/C"];
+
+/// The options of cargo nextest with a value that cargo test doesn't have, or that mean something else to it
+const NEXTEST_OPTIONS: &[&str] = &[
+    "--profile",
+    "-P",
+    "--retries",
+    "--max-fail",
+    "--test-threads",
+    "--failure-output",
+    "--success-output",
+    "--status-level",
+    "--final-status-level",
+    "--partition",
+    "--run-ignored",
+    "--no-tests",
+    "--show-progress",
+];
+/// The flags of cargo nextest that cargo test doesn't have, or handles differently
+const NEXTEST_FLAGS: &[&str] = &["--fail-fast", "--no-fail-fast", "--no-capture", "--nocapture", "--hide-progress-bar"];
+
+/// A single named command in the pipeline
+#[derive(Debug, Clone)]
+pub struct Command {
+    /// The name used by --only and --skip
+    pub name: String,
+    /// The program followed by its arguments
+    pub args: Vec<String>,
+    /// Extra environment variables for the command
+    pub env: Vec<(String, String)>,
+    /// The command and everything it started are killed if it runs for longer than this
+    pub timeout: Option<Duration>,
+    /// How many times to run the command again when it fails, before the run counts as failed
+    pub retries: u32,
+    /// Run the command at the same time as the parallel commands next to it
+    pub parallel: bool,
+    /// The names of the commands that have to pass before this one runs
+    pub depends_on: Vec<String>,
+    /// The directory to run the command in, relative to the crate directory
+    pub cwd: Option<PathBuf>,
+    /// Run the command at a lower priority, with this niceness
+    pub nice: Option<u8>,
+    /// The most memory in bytes the command and everything it starts can use together
+    pub memory_limit: Option<u64>,
+    /// How many CPUs the command and everything it starts can use together, which can be a fraction
+    pub cpu_limit: Option<f64>,
+    /// Run the command in a terminal of its own, so it writes its output like it does to a terminal
+    pub pty: bool,
+    /// Print every line of output after the name of the command, even when it runs on its own
+    pub prefix: bool,
+    /// Only print the output of the command if it fails
+    pub quiet: bool,
+    /// Only run the command after the fast ones passed, and if nothing changed while they ran
+    pub slow: bool,
+    /// How often a scheduled command runs
+    pub every: Option<Duration>,
+    /// Run the command on another machine over SSH
+    pub ssh: Option<Ssh>,
+    /// Run the command in a container
+    pub container: Option<Container>,
+    /// The command formats the sources, so the files it writes don't start another run
+    pub formats: bool,
+}
+
+impl Command {
+    /// Create a command that is named after what it runs
+    ///
+    /// Cargo commands are named after the cargo subcommand, like check for `cargo +nightly check`, and anything else
+    /// after the file name of the program.
+    pub fn new(args: Vec<String>) -> Command {
+        let subcommand = match args.split_first() {
+            Some((program, rest)) if program == "cargo" => rest.iter().find(|arg| !arg.starts_with(['+', '-'])),
+            _ => None,
+        };
+        let name = match (subcommand, args.first()) {
+            (Some(subcommand), _) => subcommand.clone(),
+            (None, Some(program)) => Path::new(program)
+                .file_name()
+                .map_or_else(|| program.clone(), |name| name.to_string_lossy().into()),
+            (None, None) => String::new(),
+        };
+        Command::named(name, args)
+    }
+
+    pub fn named<T: Into<String>>(name: T, args: Vec<String>) -> Command {
+        Command {
+            name: name.into(),
+            args,
+            env: Vec::new(),
+            timeout: None,
+            retries: 0,
+            parallel: false,
+            depends_on: Vec::new(),
+            cwd: None,
+            nice: None,
+            memory_limit: None,
+            cpu_limit: None,
+            pty: false,
+            prefix: false,
+            quiet: false,
+            slow: false,
+            every: None,
+            ssh: None,
+            container: None,
+            formats: false,
+        }
+    }
+
+    /// Append arguments after the `--` that separates the arguments for cargo from the ones for the tool it runs
+    ///
+    /// The separator is only added if the command doesn't already have one.
+    pub fn push_tool_args<I: IntoIterator<Item = String>>(&mut self, args: I) {
+        if !self.args.iter().any(|arg| arg == "--") {
+            self.args.push("--".into());
+        }
+        self.args.extend(args);
+    }
+
+    /// Run the command with another toolchain, like `cargo +nightly check`
+    ///
+    /// This only affects commands that run cargo or cross, and replaces any toolchain that is already given.
+    pub fn set_toolchain(&mut self, toolchain: &str) {
+        if self.runs_cargo() {
+            if self.args.get(1).is_some_and(|arg| arg.starts_with('+')) {
+                self.args.remove(1);
+            }
+            self.args.insert(1, format!("+{}", toolchain.trim_start_matches('+')));
+        }
+    }
+
+    /// Check if the command runs cargo, or cross which takes the same arguments
+    pub fn runs_cargo(&self) -> bool {
+        self.args.first().is_some_and(|program| program == "cargo" || program == "cross")
+    }
+
+    /// The cargo subcommand the command runs, like test for `cargo +nightly test --lib`
+    pub fn cargo_subcommand(&self) -> Option<&str> {
+        if !self.runs_cargo() {
+            return None;
+        }
+        self.args[1..]
+            .iter()
+            .find(|arg| !arg.starts_with(['+', '-']))
+            .map(String::as_str)
+    }
+
+    /// Check if the command runs cargo to build the code, so the JSON messages from cargo tell what the compiler said
+    /// and whether the build failed
+    ///
+    /// Commands that already choose the format of the messages are left alone.
+    pub fn json_messages(&self) -> bool {
+        const BUILDS: &[&str] = &["build", "b", "check", "c", "clippy", "test", "t", "bench", "run", "r", "doc", "d"];
+        self.cargo_subcommand().is_some_and(|subcommand| BUILDS.contains(&subcommand))
+            && !self
+                .args
+                .iter()
+                .take_while(|arg| *arg != "--")
+                .any(|arg| arg.starts_with("--message-format"))
+    }
+
+    /// Limit the command to some packages in the workspace with `-p`, if it runs cargo to build them
+    ///
+    /// Commands that already pick the packages, or point to another manifest, are left alone.
+    pub fn select_packages(&mut self, packages: &[String]) {
+        const BUILDS: &[&str] = &["build", "b", "check", "c", "clippy", "test", "t", "nextest", "bench", "doc", "d"];
+        const SELECTS: &[&str] = &["-p", "--package", "--workspace", "--all", "--exclude", "--manifest-path"];
+        let end = self.args.iter().position(|arg| arg == "--").unwrap_or(self.args.len());
+        let selects = self.args[..end]
+            .iter()
+            .any(|arg| SELECTS.iter().any(|select| arg == select || arg.starts_with(&format!("{}=", select))));
+        if selects || !self.cargo_subcommand().is_some_and(|subcommand| BUILDS.contains(&subcommand)) {
+            return;
+        }
+        let args = packages.iter().flat_map(|package| ["-p".to_string(), package.clone()]);
+        self.args.splice(end..end, args);
+    }
+
+    /// Only run some of the tests, picking the integration tests by name with `--test` and the rest by filters on the
+    /// names of the tests
+    pub fn select_tests(&mut self, targets: &[String], filters: &[String]) {
+        let end = self.args.iter().position(|arg| arg == "--").unwrap_or(self.args.len());
+        let args = targets.iter().flat_map(|name| ["--test".to_string(), name.clone()]);
+        self.args.splice(end..end, args);
+        if !filters.is_empty() {
+            self.push_tool_args(filters.iter().cloned());
+        }
+    }
+
+    /// Only run the doc tests of the packages, with `--doc`
+    ///
+    /// cargo nextest doesn't run doc tests, so they are run with cargo test instead. The options only nextest has are
+    /// left out, the cargo profile that is passed to nextest with `--cargo-profile` goes back to `--profile`, and the
+    /// environment for its JSON is left out.
+    pub fn select_doc_tests(&mut self, packages: &[String]) {
+        if let Some(index) = self.args.iter().position(|arg| arg == "nextest").filter(|_| self.runs_nextest()) {
+            self.args.splice(index..(index + 2).min(self.args.len()), ["test".to_string()]);
+            let end = self.args.iter().position(|arg| arg == "--").unwrap_or(self.args.len());
+            let tool_args = self.args.split_off(end);
+            let mut args = std::mem::take(&mut self.args).into_iter();
+            while let Some(arg) = args.next() {
+                let (name, has_value) = match arg.split_once('=') {
+                    Some((name, _)) => (name, true),
+                    None if arg.starts_with("-P") && arg.len() > 2 => ("-P", true),
+                    None => (arg.as_str(), false),
+                };
+                if NEXTEST_OPTIONS.contains(&name) {
+                    if !has_value {
+                        args.next();
+                    }
+                    continue;
+                }
+                if NEXTEST_FLAGS.contains(&name) {
+                    continue;
+                }
+                self.args.push(match arg.strip_prefix("--cargo-profile") {
+                    Some(profile) => format!("--profile{}", profile),
+                    None => arg,
+                });
+            }
+            self.args.extend(tool_args);
+            self.env.retain(|(key, _)| key != "NEXTEST_EXPERIMENTAL_LIBTEST_JSON");
+        }
+        let end = self.args.iter().position(|arg| arg == "--").unwrap_or(self.args.len());
+        self.args.insert(end, "--doc".into());
+        self.select_packages(packages);
+    }
+
+    /// Check if the command runs the tests with cargo, where the tests can fail after the code compiled
+    pub fn runs_tests(&self) -> bool {
+        matches!(self.cargo_subcommand(), Some("test" | "t")) || self.runs_nextest()
+    }
+
+    /// Check if the command runs the tests with `cargo nextest run`
+    pub fn runs_nextest(&self) -> bool {
+        self.cargo_subcommand() == Some("nextest")
+            && self.args.iter().skip_while(|arg| *arg != "nextest").nth(1).is_some_and(|arg| arg == "run" || arg == "r")
+    }
+
+    /// Check if the results of the tests can be read from the output, which cargo nextest writes as JSON when asked
+    pub fn nextest_messages(&self) -> bool {
+        self.runs_nextest()
+            && !self
+                .args
+                .iter()
+                .take_while(|arg| *arg != "--")
+                .any(|arg| arg.starts_with("--message-format"))
+    }
+
+    /// Check if the command runs cargo deny check, where the findings can be read from the output as JSON
+    pub fn deny_messages(&self) -> bool {
+        self.cargo_subcommand() == Some("deny")
+            && self.args.iter().any(|arg| arg == "check")
+            && !self.args.iter().any(|arg| arg == "-f" || arg.starts_with("--format"))
+    }
+
+    /// Make the warnings from the compiler and clippy fail the command, by adding `-Dwarnings` to RUSTFLAGS
+    ///
+    /// The flag is added after the ones the command already has in its environment, or auto-check-rs has in its own.
+    /// For cargo doc it is added to RUSTDOCFLAGS as well, so the warnings from rustdoc fail it too.
+    pub fn deny_warnings(&mut self) {
+        self.add_flag("RUSTFLAGS", "-Dwarnings");
+        if matches!(self.cargo_subcommand(), Some("doc" | "d")) {
+            self.deny_doc_warnings();
+        }
+    }
+
+    /// Make the warnings from rustdoc, like broken intra-doc links, fail the command by adding `-Dwarnings` to
+    /// RUSTDOCFLAGS
+    pub fn deny_doc_warnings(&mut self) {
+        self.add_flag("RUSTDOCFLAGS", "-Dwarnings");
+    }
+
+    /// Add a flag to a variable like RUSTFLAGS after the flags that are already set, unless it is one of them
+    fn add_flag(&mut self, key: &str, flag: &str) {
+        let existing = match self.env.iter().rposition(|(name, _)| name == key) {
+            Some(index) => Some(self.env.remove(index).1),
+            None => std::env::var(key).ok(),
+        };
+        let flags = match existing.filter(|flags| !flags.trim().is_empty()) {
+            Some(flags) if flags.split_whitespace().any(|existing| existing == flag) => flags,
+            Some(flags) => format!("{} {}", flags.trim(), flag),
+            None => flag.into(),
+        };
+        self.env.push((key.into(), flags));
+    }
+
+    /// Replace the placeholders in the arguments with their values
+    ///
+    /// An argument that is only a placeholder becomes one argument for each value, and a placeholder inside another
+    /// argument, like the line run by the shell, is replaced by the values quoted and separated the way a shell would.
+    pub fn expand(&mut self, info: &RunInfo) {
+        let mut args = Vec::with_capacity(self.args.len());
+        for arg in self.args.drain(..) {
+            if let Some(values) = info.values(&arg) {
+                args.extend(values);
+                continue;
+            }
+            let mut arg = arg;
+            for placeholder in ["{changed_files}", "{changed_crates}", "{crate_dir}"] {
+                if arg.contains(placeholder) {
+                    let values = info.values(placeholder).unwrap_or_default();
+                    arg = arg.replace(placeholder, &shell_words::join(values));
+                }
+            }
+            args.push(arg);
+        }
+        self.args = args;
+    }
+
+    /// Create a command from a line that is run by the shell, so it can use pipes, `&&` and variables
+    ///
+    /// The command is named after the program at the start of the line, the same as it would be without the shell.
+    pub fn shell(line: &str) -> Command {
+        if line.trim().is_empty() {
+            return Command::new(Vec::new());
+        }
+        let name = shell_words::split(line)
+            .ok()
+            .map(|args| Command::new(args).name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| SHELL[0].into());
+        let mut args: Vec<String> = SHELL.iter().map(|arg| arg.to_string()).collect();
+        args.push(line.into());
+        Command::named(name, args)
+    }
+
+    /// Create a command from a line that is split into arguments like a shell would, or run by the shell if `shell`
+    pub fn parse(line: &str, shell: bool) -> Result<Command, shell_words::ParseError> {
+        if shell {
+            Ok(Command::shell(line))
+        } else {
+            Ok(Command::new(shell_words::split(line)?))
+        }
+    }
+}
+
+/// What started a run, for the placeholders in the arguments of the commands and their environment
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub trigger: Trigger,
+    /// The full paths of the files that changed, including the ones that were removed
+    pub changed_files: Vec<String>,
+    /// The names of the packages in the workspace that the changed files belong to
+    pub changed_crates: Vec<String>,
+    pub crate_dir: String,
+}
+
+impl RunInfo {
+    /// Fill in the placeholders in the commands and add the environment variables for the run
+    pub fn apply(&self, commands: &mut [Command]) {
+        for cmd in commands.iter_mut() {
+            cmd.expand(self);
+            cmd.env.extend(self.env());
+        }
+    }
+
+    /// The environment variables that tell the commands what started the run, with the lists one value per line
+    pub fn env(&self) -> Vec<(String, String)> {
+        vec![
+            ("AUTO_CHECK_TRIGGER".into(), self.trigger.to_string()),
+            ("AUTO_CHECK_CHANGED_FILES".into(), self.changed_files.join("\n")),
+            ("AUTO_CHECK_CHANGED_CRATES".into(), self.changed_crates.join("\n")),
+            ("AUTO_CHECK_CRATE_DIR".into(), self.crate_dir.clone()),
+        ]
+    }
+
+    /// The values for a placeholder like `{changed_files}`, or None if there is no such placeholder
+    fn values(&self, placeholder: &str) -> Option<Vec<String>> {
+        match placeholder {
+            "{changed_files}" => Some(self.changed_files.clone()),
+            "{changed_crates}" => Some(self.changed_crates.clone()),
+            "{crate_dir}" => Some(vec![self.crate_dir.clone()]),
+            _ => None,
+        }
+    }
+}
+
+/// The arguments of a command in the config file, either a list or a line split like a shell would
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CommandArgs {
+    Args(Vec<String>),
+    Line(String),
+}
+
+impl CommandArgs {
+    /// Create the command, where a line is run by the shell if `shell` is set
+    fn to_command(&self, shell: bool) -> Result<Command, shell_words::ParseError> {
+        match self {
+            CommandArgs::Line(line) => Command::parse(line, shell),
+            CommandArgs::Args(args) => Ok(Command::new(args.clone())),
+        }
+    }
+}
+
+/// A command in the config file
+///
+/// This is either just the arguments, or a table with the arguments in `cmd` together with the other settings for
+/// the command.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CommandConfig {
+    Args(CommandArgs),
+    Table(CommandTable),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CommandTable {
+    pub name: Option<String>,
+    pub cmd: CommandArgs,
+    /// Run cargo with this toolchain, like `cargo +nightly`
+    pub toolchain: Option<String>,
+    /// Run the line in cmd through the shell, overriding --shell
+    pub shell: Option<bool>,
+    /// Kill the command if it runs for longer than this many seconds, overriding --timeout, where 0 means no timeout
+    pub timeout: Option<u64>,
+    /// Limit the memory the command can use, overriding --memory-limit
+    pub memory_limit: Option<Size>,
+    /// Limit the number of CPUs the command can use, overriding --cpu-limit
+    pub cpu_limit: Option<f64>,
+    /// How many times to run the command again when it fails
+    #[serde(default)]
+    pub retries: u32,
+    /// Run the command at the same time as the parallel commands next to it
+    #[serde(default)]
+    pub parallel: bool,
+    /// The names of the commands that have to pass before this one runs
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// The directory to run the command in, relative to the crate directory
+    pub cwd: Option<PathBuf>,
+    /// Only run the command after the fast ones passed, and if nothing changed while they ran
+    #[serde(default)]
+    pub slow: bool,
+    /// How often to run the command, which is only for the commands in schedule
+    pub every: Option<Interval>,
+    /// Extra environment variables for the command
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CommandConfig {
+    /// Create the command, where `shell` is used for the commands written as a line that don't say otherwise
+    pub fn to_command(&self, shell: bool) -> Result<Command, shell_words::ParseError> {
+        match self {
+            CommandConfig::Args(args) => args.to_command(shell),
+            CommandConfig::Table(table) => {
+                let mut cmd = table.cmd.to_command(table.shell.unwrap_or(shell))?;
+                if let Some(name) = &table.name {
+                    cmd.name = name.clone();
+                }
+                if let Some(toolchain) = &table.toolchain {
+                    cmd.set_toolchain(toolchain);
+                }
+                cmd.env.extend(table.env.iter().map(|(key, value)| (key.clone(), value.clone())));
+                // A timeout of 0 is kept, so it turns off the default one when the settings apply it
+                cmd.timeout = table.timeout.map(Duration::from_secs);
+                cmd.memory_limit = table.memory_limit.map(|size| size.0);
+                cmd.cpu_limit = table.cpu_limit;
+                cmd.retries = table.retries;
+                cmd.parallel = table.parallel;
+                cmd.depends_on = table.depends_on.clone();
+                cmd.cwd = table.cwd.clone();
+                cmd.slow = table.slow;
+                cmd.every = table.every.map(|every| every.0);
+                Ok(cmd)
+            },
+        }
+    }
+}
@@ -0,0 +1,741 @@
+use crate::command::{CommandArgs, CommandConfig};
+use crate::settings::{Backend, Color, Debounce, Preset, Size};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The names of the configuration file, in the order they are searched for
+pub const FILE_NAMES: &[&str] = &[".auto-check.toml", "auto-check.toml"];
+
+/// The prefix of the environment variables that override the configuration file
+pub const ENV_PREFIX: &str = "AUTO_CHECK_";
+
+/// The contents of the configuration file
+///
+/// Everything is optional, and anything that is also given on the command line is overridden by it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Verbosity level, the same as the number of -v flags
+    pub verbose: Option<usize>,
+    /// When to use colors in the log and in the output of the commands
+    pub color: Option<Color>,
+    /// Only print the output of the commands that fail
+    pub quiet: Option<bool>,
+    /// Delay in milliseconds before triggering
+    pub delay: Option<u64>,
+    /// When to run after a change
+    pub debounce: Option<Debounce>,
+    /// The longest time in milliseconds to wait after the first change
+    pub max_wait: Option<u64>,
+    /// The shortest time in milliseconds from the start of one run to the start of the next
+    pub min_interval: Option<u64>,
+    /// Poll for changes with this interval in milliseconds instead of using the native file system events
+    pub poll: Option<u64>,
+    /// The backend to watch the file system with
+    pub backend: Option<Backend>,
+    /// Watch the directories that symbolic links point to
+    pub follow_symlinks: Option<bool>,
+    /// Files and directories outside the crate directory that should also be watched, relative to the crate directory
+    pub watch: Vec<PathBuf>,
+    /// Only trigger on changes to paths in the crate directory matching these globs
+    pub include: Vec<String>,
+    /// Changes to paths matching these globs don't run anything if nothing else changed
+    pub no_op: Vec<String>,
+    /// Only trigger on changes to files with these extensions
+    pub ext: Vec<String>,
+    /// Only changes to files tracked by git trigger a run
+    pub tracked_only: Option<bool>,
+    /// Changes to files larger than this never trigger a run
+    pub max_file_size: Option<Size>,
+    /// Creating or touching this file starts a run right away, relative to the crate directory
+    pub trigger_file: Option<PathBuf>,
+    /// Patterns for the temporary files from editors, replacing the built-in list
+    pub editor_ignore: Option<Vec<String>>,
+    /// Extra patterns in .gitignore format for paths that should never trigger a run
+    pub ignore: Vec<String>,
+    /// Run once after startup without waiting for a change
+    pub run_first: Option<bool>,
+    /// Run the commands once and exit with the status of the run instead of watching
+    pub once: Option<bool>,
+    /// Stop the running commands and start over when something changes
+    pub restart_on_change: Option<bool>,
+    /// The defaults to use for the built-in commands
+    pub preset: Option<Preset>,
+    /// Run cargo fmt --check
+    pub fmt: Option<bool>,
+    /// Format the code with cargo fmt instead of only checking it
+    pub fix_fmt: Option<bool>,
+    /// Features to enable for check, build, clippy, test and doc
+    pub features: Vec<String>,
+    /// Enable all features for check, build, clippy, test and doc
+    pub all_features: Option<bool>,
+    /// Disable the default features for check, build, clippy, test and doc
+    pub no_default_features: Option<bool>,
+    /// Build in release mode
+    pub release: Option<bool>,
+    /// Build with the named cargo profile
+    pub cargo_profile: Option<String>,
+    /// Build for the target triple
+    pub target: Option<String>,
+    /// Run the tests using cross when the target can't run on this machine
+    pub cross: Option<bool>,
+    /// Run the built-in cargo commands with this toolchain
+    pub toolchain: Option<String>,
+    /// Limit the number of parallel jobs for every cargo command
+    pub jobs: Option<u32>,
+    /// Run cargo check
+    pub check: Option<bool>,
+    /// Extra arguments for cargo check
+    pub check_args: Option<String>,
+    /// Run cargo build
+    pub build: Option<bool>,
+    /// Extra arguments for cargo build
+    pub build_args: Option<String>,
+    /// Run cargo clippy
+    pub clippy: Option<bool>,
+    /// Extra arguments for cargo clippy
+    pub clippy_args: Option<String>,
+    /// Run cargo test
+    pub test: Option<bool>,
+    /// Extra arguments for cargo test
+    pub test_args: Option<String>,
+    /// Run the tests with cargo nextest, defaults to whether it is installed
+    pub nextest: Option<bool>,
+    /// Run cargo audit when the dependencies change
+    pub audit: Option<bool>,
+    /// Run cargo deny check when the dependencies change
+    pub deny: Option<bool>,
+    /// Run cargo doc --no-deps
+    pub doc: Option<bool>,
+    /// Extra arguments for cargo doc
+    pub doc_args: Option<String>,
+    /// Run the specified commands after the other checks, split into arguments like a shell would
+    pub custom_cmds: Vec<String>,
+    /// Run the commands written as a line through the shell
+    pub shell: Option<bool>,
+    /// Kill a command that runs for longer than this many seconds
+    pub timeout: Option<u64>,
+    /// Limit the memory each command can use
+    pub memory_limit: Option<Size>,
+    /// Limit the number of CPUs each command can use
+    pub cpu_limit: Option<f64>,
+    /// Run the commands at a lower priority, with this niceness
+    pub nice: Option<u8>,
+    /// Run the commands in a terminal of their own
+    pub pty: Option<bool>,
+    /// Run the commands on this host over SSH
+    pub ssh: Option<String>,
+    /// The directory of the crate on the remote machine
+    pub remote_dir: Option<String>,
+    /// Copy the crate directory to the remote machine before every run
+    pub sync: Option<bool>,
+    /// Run the commands in a container of this image
+    pub container: Option<String>,
+    /// The program that runs the containers
+    pub container_engine: Option<String>,
+    /// Other arguments for the container engine
+    pub container_args: Option<String>,
+    /// Print every line of output after the name of the command
+    pub prefix: Option<bool>,
+    /// Build in a target directory of its own, inside the one of the workspace
+    pub isolated_target_dir: Option<bool>,
+    /// Fail the cargo commands when there are warnings
+    pub deny_warnings: Option<bool>,
+    /// Wait for other cargo processes to release their locks before running the commands
+    pub wait_for_lock: Option<bool>,
+    /// Replaces the built-in check, build, clippy, test and doc commands when given
+    pub commands: Option<Vec<CommandConfig>>,
+    /// The commands to run when Cargo.lock, a Cargo.toml or deny.toml changes, instead of the others if nothing else
+    /// changed
+    pub lockfile_commands: Vec<CommandConfig>,
+    /// Other commands to run for changes below some paths
+    pub routes: Vec<RouteConfig>,
+    /// The commands to run after all the commands passed
+    pub on_success: Vec<CommandConfig>,
+    /// The commands to run after any of the commands failed
+    pub on_failure: Vec<CommandConfig>,
+    /// The commands to run every so often, whether anything changed or not
+    pub schedule: Vec<CommandConfig>,
+    /// Extra environment variables for the commands with the given names, including the built-in ones
+    pub command_env: BTreeMap<String, BTreeMap<String, String>>,
+    /// Run the rest of the commands when one fails
+    pub keep_going: Option<bool>,
+    /// Only run the built-in clippy and test commands after check passed
+    pub tiered: Option<bool>,
+    /// Only run the commands that failed until they pass
+    pub failed_first: Option<bool>,
+    /// Only build the packages in the workspace that the changes affect
+    pub affected_only: Option<bool>,
+    /// Only run the tests in the changed test files when nothing else changed
+    pub targeted_tests: Option<bool>,
+    /// Only run the doc tests when nothing but doc comments changed
+    pub doc_tests: Option<bool>,
+    /// Run all the tests after this many targeted runs in a row
+    pub full_test_every: Option<u32>,
+    /// Only run the commands with these names
+    pub only: Vec<String>,
+    /// Don't run the commands with these names
+    pub skip: Vec<String>,
+    /// Named sets of commands that can be selected with --profile
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named profile from the configuration file
+///
+/// Anything set in the profile overrides the top level of the file when the profile is selected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub delay: Option<u64>,
+    pub debounce: Option<Debounce>,
+    pub max_wait: Option<u64>,
+    pub min_interval: Option<u64>,
+    pub once: Option<bool>,
+    pub restart_on_change: Option<bool>,
+    pub preset: Option<Preset>,
+    pub fmt: Option<bool>,
+    pub fix_fmt: Option<bool>,
+    pub features: Option<Vec<String>>,
+    pub all_features: Option<bool>,
+    pub no_default_features: Option<bool>,
+    pub release: Option<bool>,
+    pub cargo_profile: Option<String>,
+    pub target: Option<String>,
+    pub cross: Option<bool>,
+    pub toolchain: Option<String>,
+    pub jobs: Option<u32>,
+    pub check: Option<bool>,
+    pub check_args: Option<String>,
+    pub build: Option<bool>,
+    pub build_args: Option<String>,
+    pub clippy: Option<bool>,
+    pub clippy_args: Option<String>,
+    pub test: Option<bool>,
+    pub test_args: Option<String>,
+    pub nextest: Option<bool>,
+    pub audit: Option<bool>,
+    pub deny: Option<bool>,
+    pub doc: Option<bool>,
+    pub doc_args: Option<String>,
+    pub custom_cmds: Option<Vec<String>>,
+    pub commands: Option<Vec<CommandConfig>>,
+    pub lockfile_commands: Option<Vec<CommandConfig>>,
+    pub routes: Option<Vec<RouteConfig>>,
+    pub on_success: Option<Vec<CommandConfig>>,
+    pub on_failure: Option<Vec<CommandConfig>>,
+    pub schedule: Option<Vec<CommandConfig>>,
+    pub keep_going: Option<bool>,
+    pub tiered: Option<bool>,
+    pub failed_first: Option<bool>,
+    pub affected_only: Option<bool>,
+    pub targeted_tests: Option<bool>,
+    pub doc_tests: Option<bool>,
+    pub full_test_every: Option<u32>,
+    pub ssh: Option<String>,
+    pub remote_dir: Option<String>,
+    pub sync: Option<bool>,
+    pub container: Option<String>,
+    pub container_engine: Option<String>,
+    pub container_args: Option<String>,
+    pub only: Option<Vec<String>>,
+    pub skip: Option<Vec<String>>,
+}
+
+/// Commands to run for changes below a path, instead of the normal ones
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RouteConfig {
+    /// The path the changes have to be below, relative to the crate directory
+    pub path: PathBuf,
+    /// The names of the normal commands to run
+    #[serde(default)]
+    pub only: Vec<String>,
+    /// Other commands to run after the ones from only
+    #[serde(default)]
+    pub commands: Vec<CommandConfig>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    EmptyCommand(PathBuf),
+    InvalidCommand(PathBuf, shell_words::ParseError),
+    UnknownProfile(String),
+    Env(String, String),
+    /// An entry in schedule without every
+    MissingEvery(PathBuf),
+    /// Every on a command that isn't in schedule
+    UnexpectedEvery(PathBuf),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "Failed to read {}: {}", path.to_string_lossy(), e),
+            Error::Parse(path, e) => write!(f, "Failed to parse {}: {}", path.to_string_lossy(), e),
+            Error::EmptyCommand(path) => write!(f, "Empty entry in commands in {}", path.to_string_lossy()),
+            Error::InvalidCommand(path, e) => {
+                write!(f, "Failed to parse an entry in commands in {}: {}", path.to_string_lossy(), e)
+            },
+            Error::UnknownProfile(name) => write!(f, "No profile named {:?} in the config file", name),
+            Error::Env(name, value) => write!(f, "Invalid value for {}: {:?}", name, value),
+            Error::MissingEvery(path) => {
+                write!(f, "Every entry in schedule in {} needs every, like every = \"24h\"", path.to_string_lossy())
+            },
+            Error::UnexpectedEvery(path) => {
+                write!(f, "Only the entries in schedule in {} can have every", path.to_string_lossy())
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Find the configuration file in the crate directory if there is one
+    pub fn find(crate_dir: &Path) -> Option<PathBuf> {
+        FILE_NAMES
+            .iter()
+            .map(|name| crate_dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Check if the path is one of the possible configuration files in the crate directory
+    pub fn is_config_file(crate_dir: &Path, path: &Path) -> bool {
+        path.parent() == Some(crate_dir)
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| FILE_NAMES.contains(&name))
+    }
+
+    /// Load the configuration file from the crate directory, or the default if there is none
+    pub fn load(crate_dir: &Path) -> Result<(Config, Option<PathBuf>), Error> {
+        match Config::find(crate_dir) {
+            Some(path) => Ok((Config::from_file(&path)?, Some(path))),
+            None => Ok((Config::default(), None)),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let text = std::fs::read_to_string(path).map_err(|e| Error::Io(path.into(), e))?;
+        let config: Config = toml::from_str(&text).map_err(|e| Error::Parse(path.into(), e))?;
+
+        let all_commands = config
+            .commands
+            .iter()
+            .chain([&config.lockfile_commands, &config.on_success, &config.on_failure])
+            .chain(config.profiles.values().filter_map(|p| p.commands.as_ref()))
+            .chain(config.profiles.values().filter_map(|p| p.lockfile_commands.as_ref()))
+            .chain(config.profiles.values().filter_map(|p| p.on_success.as_ref()))
+            .chain(config.profiles.values().filter_map(|p| p.on_failure.as_ref()))
+            .chain(config.routes.iter().map(|route| &route.commands))
+            .chain(config.profiles.values().filter_map(|p| p.routes.as_ref()).flatten().map(|route| &route.commands));
+        for cmd in all_commands.flatten() {
+            let cmd = cmd.to_command(false).map_err(|e| Error::InvalidCommand(path.into(), e))?;
+            if cmd.args.is_empty() {
+                return Err(Error::EmptyCommand(path.into()));
+            }
+            if cmd.every.is_some() {
+                return Err(Error::UnexpectedEvery(path.into()));
+            }
+        }
+        let schedules = config.profiles.values().filter_map(|p| p.schedule.as_ref());
+        for cmd in config.schedule.iter().chain(schedules.flatten()) {
+            let cmd = cmd.to_command(false).map_err(|e| Error::InvalidCommand(path.into(), e))?;
+            if cmd.args.is_empty() {
+                return Err(Error::EmptyCommand(path.into()));
+            }
+            if cmd.every.is_none() {
+                return Err(Error::MissingEvery(path.into()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Override the top level settings with the ones from the named profile
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), Error> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or_else(|| Error::UnknownProfile(name.into()))?;
+
+        self.delay = profile.delay.or(self.delay);
+        self.debounce = profile.debounce.or(self.debounce);
+        self.max_wait = profile.max_wait.or(self.max_wait);
+        self.min_interval = profile.min_interval.or(self.min_interval);
+        self.once = profile.once.or(self.once);
+        self.restart_on_change = profile.restart_on_change.or(self.restart_on_change);
+        self.preset = profile.preset.or(self.preset);
+        self.fmt = profile.fmt.or(self.fmt);
+        self.fix_fmt = profile.fix_fmt.or(self.fix_fmt);
+        if let Some(features) = profile.features {
+            self.features = features;
+        }
+        self.all_features = profile.all_features.or(self.all_features);
+        self.no_default_features = profile.no_default_features.or(self.no_default_features);
+        self.release = profile.release.or(self.release);
+        self.cargo_profile = profile.cargo_profile.or(self.cargo_profile.take());
+        self.target = profile.target.or(self.target.take());
+        self.cross = profile.cross.or(self.cross);
+        self.toolchain = profile.toolchain.or(self.toolchain.take());
+        self.jobs = profile.jobs.or(self.jobs);
+        self.check = profile.check.or(self.check);
+        self.check_args = profile.check_args.or(self.check_args.take());
+        self.build = profile.build.or(self.build);
+        self.build_args = profile.build_args.or(self.build_args.take());
+        self.clippy = profile.clippy.or(self.clippy);
+        self.clippy_args = profile.clippy_args.or(self.clippy_args.take());
+        self.test = profile.test.or(self.test);
+        self.test_args = profile.test_args.or(self.test_args.take());
+        self.nextest = profile.nextest.or(self.nextest);
+        self.audit = profile.audit.or(self.audit);
+        self.deny = profile.deny.or(self.deny);
+        self.doc = profile.doc.or(self.doc);
+        self.doc_args = profile.doc_args.or(self.doc_args.take());
+        if let Some(custom_cmds) = profile.custom_cmds {
+            self.custom_cmds = custom_cmds;
+        }
+        if let Some(commands) = profile.commands {
+            self.commands = Some(commands);
+        }
+        if let Some(lockfile_commands) = profile.lockfile_commands {
+            self.lockfile_commands = lockfile_commands;
+        }
+        if let Some(routes) = profile.routes {
+            self.routes = routes;
+        }
+        if let Some(on_success) = profile.on_success {
+            self.on_success = on_success;
+        }
+        if let Some(on_failure) = profile.on_failure {
+            self.on_failure = on_failure;
+        }
+        if let Some(schedule) = profile.schedule {
+            self.schedule = schedule;
+        }
+        self.keep_going = profile.keep_going.or(self.keep_going);
+        self.tiered = profile.tiered.or(self.tiered);
+        self.failed_first = profile.failed_first.or(self.failed_first);
+        self.affected_only = profile.affected_only.or(self.affected_only);
+        self.targeted_tests = profile.targeted_tests.or(self.targeted_tests);
+        self.doc_tests = profile.doc_tests.or(self.doc_tests);
+        self.full_test_every = profile.full_test_every.or(self.full_test_every);
+        self.ssh = profile.ssh.or(self.ssh.take());
+        self.remote_dir = profile.remote_dir.or(self.remote_dir.take());
+        self.sync = profile.sync.or(self.sync);
+        self.container = profile.container.or(self.container.take());
+        self.container_engine = profile.container_engine.or(self.container_engine.take());
+        self.container_args = profile.container_args.or(self.container_args.take());
+        if let Some(only) = profile.only {
+            self.only = only;
+        }
+        if let Some(skip) = profile.skip {
+            self.skip = skip;
+        }
+
+        Ok(())
+    }
+
+    /// Override the settings with the AUTO_CHECK_* environment variables
+    ///
+    /// Flags like AUTO_CHECK_NO_CLIPPY accept 1/0, true/false and yes/no, and AUTO_CHECK_CMD and AUTO_CHECK_CUSTOM_CMD
+    /// take one command per line. AUTO_CHECK_EXCLUDE takes one pattern per line, which are added to the ignore list,
+    /// and AUTO_CHECK_INCLUDE one glob per line. AUTO_CHECK_ONLY and AUTO_CHECK_SKIP and AUTO_CHECK_EXT take a comma
+    /// separated list, and AUTO_CHECK_WATCH a list of paths separated like in PATH.
+    pub fn apply_env(&mut self) -> Result<(), Error> {
+        if let Some(verbose) = env_parse("VERBOSE")? {
+            self.verbose = Some(verbose);
+        }
+        if let Some(quiet) = env_bool("QUIET")? {
+            self.quiet = Some(quiet);
+        }
+        if let Some(color) = env_var("COLOR")? {
+            self.color = Some(
+                clap::ValueEnum::from_str(&color, true)
+                    .map_err(|_| Error::Env(format!("{}COLOR", ENV_PREFIX), color))?,
+            );
+        }
+        if let Some(delay) = env_parse("DELAY")? {
+            self.delay = Some(delay);
+        }
+        if let Some(debounce) = env_var("DEBOUNCE")? {
+            self.debounce = Some(
+                clap::ValueEnum::from_str(&debounce, true)
+                    .map_err(|_| Error::Env(format!("{}DEBOUNCE", ENV_PREFIX), debounce))?,
+            );
+        }
+        if let Some(max_wait) = env_parse("MAX_WAIT")? {
+            self.max_wait = Some(max_wait);
+        }
+        if let Some(min_interval) = env_parse("MIN_INTERVAL")? {
+            self.min_interval = Some(min_interval);
+        }
+        if let Some(poll) = env_parse("POLL")? {
+            self.poll = Some(poll);
+        }
+        if let Some(backend) = env_var("BACKEND")? {
+            self.backend = Some(
+                clap::ValueEnum::from_str(&backend, true)
+                    .map_err(|_| Error::Env(format!("{}BACKEND", ENV_PREFIX), backend))?,
+            );
+        }
+        if let Some(follow_symlinks) = env_bool("FOLLOW_SYMLINKS")? {
+            self.follow_symlinks = Some(follow_symlinks);
+        }
+        if let Some(watch) = std::env::var_os(format!("{}WATCH", ENV_PREFIX)) {
+            self.watch = std::env::split_paths(&watch).collect();
+        }
+        if let Some(exclude) = env_var("EXCLUDE")? {
+            self.ignore.extend(env_lines(&exclude).map(String::from));
+        }
+        if let Some(include) = env_var("INCLUDE")? {
+            self.include = env_lines(&include).map(String::from).collect();
+        }
+        if let Some(no_op) = env_var("NO_OP")? {
+            self.no_op = env_lines(&no_op).map(String::from).collect();
+        }
+        if let Some(ext) = env_var("EXT")? {
+            self.ext = env_names(&ext);
+        }
+        if let Some(tracked_only) = env_bool("TRACKED_ONLY")? {
+            self.tracked_only = Some(tracked_only);
+        }
+        if let Some(max_file_size) = env_parse("MAX_FILE_SIZE")? {
+            self.max_file_size = Some(max_file_size);
+        }
+        if let Some(trigger_file) = env_var("TRIGGER_FILE")? {
+            self.trigger_file = Some(trigger_file.into());
+        }
+        if env_bool("NO_RUN_FIRST")? == Some(true) {
+            self.run_first = Some(false);
+        }
+        if let Some(once) = env_bool("ONCE")? {
+            self.once = Some(once);
+        }
+        if let Some(restart_on_change) = env_bool("RESTART_ON_CHANGE")? {
+            self.restart_on_change = Some(restart_on_change);
+        }
+        if let Some(preset) = env_var("PRESET")? {
+            self.preset = Some(
+                clap::ValueEnum::from_str(&preset, true)
+                    .map_err(|_| Error::Env(format!("{}PRESET", ENV_PREFIX), preset))?,
+            );
+        }
+        if let Some(features) = env_var("FEATURES")? {
+            self.features = env_names(&features);
+        }
+        if let Some(all_features) = env_bool("ALL_FEATURES")? {
+            self.all_features = Some(all_features);
+        }
+        if let Some(no_default_features) = env_bool("NO_DEFAULT_FEATURES")? {
+            self.no_default_features = Some(no_default_features);
+        }
+        if let Some(release) = env_bool("RELEASE")? {
+            self.release = Some(release);
+        }
+        if let Some(cargo_profile) = env_var("CARGO_PROFILE")? {
+            self.cargo_profile = Some(cargo_profile);
+        }
+        if let Some(target) = env_var("TARGET")? {
+            self.target = Some(target);
+        }
+        if let Some(cross) = env_bool("CROSS")? {
+            self.cross = Some(cross);
+        }
+        if let Some(toolchain) = env_var("TOOLCHAIN")? {
+            self.toolchain = Some(toolchain);
+        }
+        if let Some(jobs) = env_parse("JOBS")? {
+            self.jobs = Some(jobs);
+        }
+        if let Some(fmt) = env_bool("FMT")? {
+            self.fmt = Some(fmt);
+        }
+        if env_bool("NO_FMT")? == Some(true) {
+            self.fmt = Some(false);
+        }
+        if let Some(fix_fmt) = env_bool("FIX_FMT")? {
+            self.fix_fmt = Some(fix_fmt);
+        }
+        if env_bool("NO_CHECK")? == Some(true) {
+            self.check = Some(false);
+        }
+        if let Some(build) = env_bool("BUILD")? {
+            self.build = Some(build);
+        }
+        if env_bool("NO_CLIPPY")? == Some(true) {
+            self.clippy = Some(false);
+        }
+        if env_bool("NO_TEST")? == Some(true) {
+            self.test = Some(false);
+        }
+        if let Some(check_args) = env_var("CHECK_ARGS")? {
+            self.check_args = Some(check_args);
+        }
+        if let Some(build_args) = env_var("BUILD_ARGS")? {
+            self.build_args = Some(build_args);
+        }
+        if let Some(clippy_args) = env_var("CLIPPY_ARGS")? {
+            self.clippy_args = Some(clippy_args);
+        }
+        if let Some(test_args) = env_var("TEST_ARGS")? {
+            self.test_args = Some(test_args);
+        }
+        if let Some(nextest) = env_bool("NEXTEST")? {
+            self.nextest = Some(nextest);
+        }
+        if env_bool("NO_NEXTEST")? == Some(true) {
+            self.nextest = Some(false);
+        }
+        if let Some(audit) = env_bool("AUDIT")? {
+            self.audit = Some(audit);
+        }
+        if let Some(deny) = env_bool("DENY")? {
+            self.deny = Some(deny);
+        }
+        if let Some(doc) = env_bool("DOC")? {
+            self.doc = Some(doc);
+        }
+        if let Some(doc_args) = env_var("DOC_ARGS")? {
+            self.doc_args = Some(doc_args);
+        }
+        if let Some(cmds) = env_var("CMD")? {
+            let cmds = env_lines(&cmds)
+                .map(|cmd| match shell_words::split(cmd) {
+                    // Kept as a line, so it can still be run through the shell
+                    Ok(_) => Ok(CommandConfig::Args(CommandArgs::Line(cmd.into()))),
+                    Err(_) => Err(Error::Env(format!("{}CMD", ENV_PREFIX), cmd.into())),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if !cmds.is_empty() {
+                self.commands = Some(cmds);
+            }
+        }
+        if let Some(shell) = env_bool("SHELL")? {
+            self.shell = Some(shell);
+        }
+        if let Some(timeout) = env_parse("TIMEOUT")? {
+            self.timeout = Some(timeout);
+        }
+        if let Some(memory_limit) = env_parse("MEMORY_LIMIT")? {
+            self.memory_limit = Some(memory_limit);
+        }
+        if let Some(cpu_limit) = env_parse("CPU_LIMIT")? {
+            self.cpu_limit = Some(cpu_limit);
+        }
+        if let Some(nice) = env_parse("NICE")? {
+            self.nice = Some(nice);
+        }
+        if let Some(pty) = env_bool("PTY")? {
+            self.pty = Some(pty);
+        }
+        if let Some(ssh) = env_var("SSH")? {
+            self.ssh = Some(ssh);
+        }
+        if let Some(remote_dir) = env_var("REMOTE_DIR")? {
+            self.remote_dir = Some(remote_dir);
+        }
+        if let Some(sync) = env_bool("SYNC")? {
+            self.sync = Some(sync);
+        }
+        if let Some(container) = env_var("CONTAINER")? {
+            self.container = Some(container);
+        }
+        if let Some(container_engine) = env_var("CONTAINER_ENGINE")? {
+            self.container_engine = Some(container_engine);
+        }
+        if let Some(container_args) = env_var("CONTAINER_ARGS")? {
+            self.container_args = Some(container_args);
+        }
+        if let Some(prefix) = env_bool("PREFIX")? {
+            self.prefix = Some(prefix);
+        }
+        if let Some(deny_warnings) = env_bool("DENY_WARNINGS")? {
+            self.deny_warnings = Some(deny_warnings);
+        }
+        if let Some(isolated_target_dir) = env_bool("ISOLATED_TARGET_DIR")? {
+            self.isolated_target_dir = Some(isolated_target_dir);
+        }
+        if let Some(wait_for_lock) = env_bool("WAIT_FOR_LOCK")? {
+            self.wait_for_lock = Some(wait_for_lock);
+        }
+        if let Some(custom_cmds) = env_var("CUSTOM_CMD")? {
+            self.custom_cmds = env_lines(&custom_cmds).map(String::from).collect();
+        }
+        if let Some(keep_going) = env_bool("KEEP_GOING")? {
+            self.keep_going = Some(keep_going);
+        }
+        if let Some(tiered) = env_bool("TIERED")? {
+            self.tiered = Some(tiered);
+        }
+        if let Some(failed_first) = env_bool("FAILED_FIRST")? {
+            self.failed_first = Some(failed_first);
+        }
+        if let Some(affected_only) = env_bool("AFFECTED_ONLY")? {
+            self.affected_only = Some(affected_only);
+        }
+        if let Some(targeted_tests) = env_bool("TARGETED_TESTS")? {
+            self.targeted_tests = Some(targeted_tests);
+        }
+        if let Some(doc_tests) = env_bool("DOC_TESTS")? {
+            self.doc_tests = Some(doc_tests);
+        }
+        if let Some(full_test_every) = env_parse("FULL_TEST_EVERY")? {
+            self.full_test_every = Some(full_test_every);
+        }
+        if let Some(only) = env_var("ONLY")? {
+            self.only = env_names(&only);
+        }
+        if let Some(skip) = env_var("SKIP")? {
+            self.skip = env_names(&skip);
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the value of AUTO_CHECK_{name} if it is set
+pub fn env_var(name: &str) -> Result<Option<String>, Error> {
+    let name = format!("{}{}", ENV_PREFIX, name);
+    match std::env::var(&name) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(value)) => Err(Error::Env(name, value.to_string_lossy().into())),
+    }
+}
+
+fn env_lines(value: &str) -> impl Iterator<Item = &str> {
+    value.lines().filter(|line| !line.trim().is_empty())
+}
+
+fn env_names(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Result<Option<T>, Error> {
+    match env_var(name)? {
+        Some(value) => match value.trim().parse() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(Error::Env(format!("{}{}", ENV_PREFIX, name), value)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn env_bool(name: &str) -> Result<Option<bool>, Error> {
+    match env_var(name)? {
+        Some(value) => match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "" | "0" | "false" | "no" | "off" => Ok(Some(false)),
+            _ => Err(Error::Env(format!("{}{}", ENV_PREFIX, name), value)),
+        },
+        None => Ok(None),
+    }
+}
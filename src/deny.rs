@@ -0,0 +1,129 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// A line of output from cargo deny with `--format json`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    /// Something cargo deny found or logged, as the lines it is printed as
+    Diagnostic(Vec<String>),
+    /// How many errors and warnings each of the checks found, which comes last
+    Summary(Vec<Check>),
+    /// Something else that isn't worth printing
+    Other,
+}
+
+/// What one of the checks of cargo deny, like licenses or bans, found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub errors: u32,
+    pub warnings: u32,
+}
+
+#[derive(Deserialize)]
+struct JsonLine {
+    #[serde(rename = "type")]
+    kind: String,
+    fields: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct JsonDiagnostic {
+    #[serde(alias = "level")]
+    severity: String,
+    message: String,
+    code: Option<String>,
+    #[serde(default)]
+    labels: Vec<JsonLabel>,
+    #[serde(default)]
+    notes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonLabel {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    span: String,
+}
+
+#[derive(Deserialize)]
+struct JsonCounts {
+    #[serde(default)]
+    errors: u32,
+    #[serde(default)]
+    warnings: u32,
+}
+
+/// Parse a line of output from cargo deny as JSON, returns None for anything else
+pub fn parse_line(line: &str) -> Option<Line> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    let line: JsonLine = serde_json::from_str(line).ok()?;
+    Some(match line.kind.as_str() {
+        "summary" => {
+            let checks: BTreeMap<String, JsonCounts> = serde_json::from_value(line.fields).ok()?;
+            Line::Summary(
+                checks
+                    .into_iter()
+                    .map(|(name, counts)| Check {
+                        name,
+                        errors: counts.errors,
+                        warnings: counts.warnings,
+                    })
+                    .collect(),
+            )
+        },
+        _ => match serde_json::from_value::<JsonDiagnostic>(line.fields) {
+            Ok(diagnostic) => Line::Diagnostic(render(diagnostic)),
+            Err(_) => Line::Other,
+        },
+    })
+}
+
+/// Print a diagnostic like the compiler does, with what it points to and its notes below it
+fn render(diagnostic: JsonDiagnostic) -> Vec<String> {
+    let severity = diagnostic.severity.to_lowercase();
+    let mut lines = match diagnostic.code {
+        Some(code) => vec![format!("{}[{}]: {}", severity, code, diagnostic.message)],
+        None => vec![format!("{}: {}", severity, diagnostic.message)],
+    };
+    for label in diagnostic.labels {
+        match (label.span.trim(), label.message.is_empty()) {
+            ("", true) => {},
+            ("", false) => lines.push(format!("  --> {}", label.message)),
+            (span, true) => lines.push(format!("  --> {}", span)),
+            (span, false) => lines.push(format!("  --> {} ({})", label.message, span)),
+        }
+    }
+    lines.extend(diagnostic.notes.iter().map(|note| format!("   = {}", note)));
+    lines
+}
+
+/// Sum up what the checks found, like `1 error in licenses and 2 warnings in advisories`, or None if they found nothing
+pub fn summarize(checks: &[Check]) -> Option<String> {
+    let plural = |count: u32, name: &str| format!("{} {}{}", count, name, if count == 1 { "" } else { "s" });
+    let findings: Vec<String> = checks
+        .iter()
+        .filter(|check| check.errors > 0 || check.warnings > 0)
+        .map(|check| {
+            let counts = match (check.errors, check.warnings) {
+                (errors, 0) => plural(errors, "error"),
+                (0, warnings) => plural(warnings, "warning"),
+                (errors, warnings) => format!("{} and {}", plural(errors, "error"), plural(warnings, "warning")),
+            };
+            format!("{} in {}", counts, check.name)
+        })
+        .collect();
+    match findings.split_last() {
+        None => None,
+        Some((last, [])) => Some(last.clone()),
+        Some((last, rest)) => Some(format!("{} and {}", rest.join(", "), last)),
+    }
+}
+
+/// Check if any of the checks found an error, which fails the command
+pub fn has_errors(checks: &[Check]) -> bool {
+    checks.iter().any(|check| check.errors > 0)
+}
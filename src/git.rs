@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// The files that git tracks in a directory, to only react to changes to them
+pub struct Tracked {
+    dir: PathBuf,
+    /// The index of the repository, which changes whenever files are added or removed
+    index: PathBuf,
+    files: HashSet<PathBuf>,
+}
+
+impl Tracked {
+    /// Ask git for the tracked files in the directory, returns None if it isn't in a git repository
+    pub fn new(dir: &Path) -> Option<Tracked> {
+        let index = git_dir(dir)?.join("index");
+        let mut tracked = Tracked {
+            dir: dir.into(),
+            index,
+            files: HashSet::new(),
+        };
+        tracked.reload();
+        Some(tracked)
+    }
+
+    /// Read the tracked files again, after the index changed
+    pub fn reload(&mut self) {
+        let output = match git(&self.dir, &["ls-files", "-z"]) {
+            Some(output) => output,
+            None => return,
+        };
+        self.files = output
+            .split(|&b| b == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| self.dir.join(String::from_utf8_lossy(path).as_ref()))
+            .collect();
+        log::debug!("Found {} tracked files in {}", self.files.len(), self.dir.to_string_lossy());
+    }
+
+    /// Check if git tracks a file, the path has to be absolute
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains(path)
+    }
+
+    /// The path of the index, which has to be watched to see when files are added or removed
+    pub fn index(&self) -> &Path {
+        &self.index
+    }
+}
+
+/// Find the directory git keeps its own files in for the repository the directory is in
+pub fn git_dir(dir: &Path) -> Option<PathBuf> {
+    let git_dir = git(dir, &["rev-parse", "--absolute-git-dir"])?;
+    Some(PathBuf::from(String::from_utf8_lossy(&git_dir).trim()))
+}
+
+/// Read the branch that is checked out from the HEAD file, returns None if HEAD is detached
+pub fn current_branch(head: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(head).ok()?;
+    head.trim().strip_prefix("ref: refs/heads/").map(String::from)
+}
+
+/// Run git in the directory and return the output
+///
+/// Failing is only logged at debug level, since most directories being outside a repository is not an error.
+fn git(dir: &Path, args: &[&str]) -> Option<Vec<u8>> {
+    let output = std::process::Command::new("git").args(args).current_dir(dir).output();
+    match output {
+        Ok(output) if output.status.success() => Some(output.stdout),
+        Ok(output) => {
+            log::debug!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+            None
+        },
+        Err(e) => {
+            log::debug!("Failed to run git: {}", e);
+            None
+        },
+    }
+}
@@ -0,0 +1,222 @@
+use crate::ignores::Ignores;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The files the formatting commands wrote, with the hashes of what they wrote, so the writes don't trigger a run
+static WRITTEN: Mutex<Option<HashMap<PathBuf, u64>>> = Mutex::new(None);
+/// The number of formatting commands that are running right now
+static WRITING: AtomicUsize = AtomicUsize::new(0);
+
+/// Files larger than this are not hashed, and every write to them counts as a change
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// Hashes of the contents of the watched files, used to skip writes that didn't change anything
+#[derive(Default)]
+pub struct Hashes {
+    hashes: HashMap<PathBuf, Hash>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Hash {
+    contents: u64,
+    /// The hash of the contents without the doc comments, for Rust files
+    code: u64,
+}
+
+/// How the contents of a file changed since the last time it was hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Update {
+    Unchanged,
+    /// Nothing but the doc comments changed in a Rust file
+    Docs,
+    Changed,
+}
+
+impl Hashes {
+    /// Hash every file in the directory that isn't ignored, so the first write to a file can be compared as well
+    pub fn scan(&mut self, dir: &Path, ignores: &Ignores) {
+        for path in files(dir, ignores) {
+            if let Some(hash) = hash_file(&path) {
+                self.hashes.insert(path, hash);
+            }
+        }
+    }
+
+    /// Find the files in the directory that changed since they were last hashed, including the ones that were removed
+    ///
+    /// This doesn't update the hashes. Files that are too large to be hashed can't be compared and are left out.
+    pub fn changed(&self, dir: &Path, ignores: &Ignores) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = files(dir, ignores)
+            .into_iter()
+            .filter(|path| match hash_file(path) {
+                Some(hash) => self.hashes.get(path).map(|old| old.contents) != Some(hash.contents),
+                None => false,
+            })
+            .collect();
+        changed.extend(
+            self.hashes
+                .keys()
+                .filter(|path| path.starts_with(dir) && !path.exists())
+                .cloned(),
+        );
+        changed
+    }
+
+    /// The hash of the contents of the file, as it was the last time it was hashed
+    pub fn contents(&self, path: &Path) -> Option<u64> {
+        self.hashes.get(path).map(|hash| hash.contents)
+    }
+
+    /// Move the hashes of everything below a path that was renamed
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        let moved: Vec<PathBuf> = self.hashes.keys().filter(|path| path.starts_with(from)).cloned().collect();
+        for path in moved {
+            if let (Some(hash), Ok(rest)) = (self.hashes.remove(&path), path.strip_prefix(from)) {
+                self.hashes.insert(moved_path(to, rest), hash);
+            }
+        }
+    }
+
+    /// Hash the file again, and tell how it changed since the last time
+    ///
+    /// Anything that can't be hashed, like removed or large files, is always changed.
+    pub fn update(&mut self, path: &Path) -> Update {
+        match hash_file(path) {
+            Some(hash) => match self.hashes.insert(path.into(), hash) {
+                Some(old) if old == hash => Update::Unchanged,
+                Some(old) if old.code == hash.code => Update::Docs,
+                _ => Update::Changed,
+            },
+            None => {
+                self.hashes.remove(path);
+                Update::Changed
+            },
+        }
+    }
+}
+
+/// The writes of a formatting command that is running, found by hashing the sources before and after it runs
+pub struct Writes {
+    dir: PathBuf,
+    before: HashMap<PathBuf, u64>,
+}
+
+impl Writes {
+    /// Hash the Rust files in the directory before the command runs
+    pub fn start(dir: &Path) -> Writes {
+        WRITING.fetch_add(1, Ordering::Relaxed);
+        Writes {
+            dir: dir.into(),
+            before: sources(dir),
+        }
+    }
+
+    /// Hash the Rust files again after the command ran, and remember the ones it changed
+    pub fn finish(self) {
+        let written: Vec<(PathBuf, u64)> = sources(&self.dir)
+            .into_iter()
+            .filter(|(path, hash)| self.before.get(path) != Some(hash))
+            .collect();
+        if !written.is_empty() {
+            log::debug!("Formatted {:?}", written.iter().map(|(path, _)| path).collect::<Vec<_>>());
+            let mut all = WRITTEN.lock().expect("Written poisoned");
+            all.get_or_insert_with(HashMap::new).extend(written);
+        }
+    }
+}
+
+impl Drop for Writes {
+    fn drop(&mut self) {
+        WRITING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Check if a formatting command is running, so the writes can't be told apart from the ones of the user yet
+pub fn is_writing() -> bool {
+    WRITING.load(Ordering::Relaxed) > 0
+}
+
+/// Check if a formatting command wrote the file with these contents, forgetting about the write if it did
+pub fn take_written(path: &Path, hash: u64) -> bool {
+    let mut written = WRITTEN.lock().expect("Written poisoned");
+    match written.as_mut() {
+        Some(written) if written.get(path) == Some(&hash) => written.remove(path).is_some(),
+        _ => false,
+    }
+}
+
+/// Hash the Rust files in the directory that git doesn't ignore
+fn sources(dir: &Path) -> HashMap<PathBuf, u64> {
+    ignore::WalkBuilder::new(dir)
+        .build()
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "rs"))
+        .filter_map(|entry| Some((entry.path().to_path_buf(), hash_file(entry.path())?.contents)))
+        .collect()
+}
+
+/// The new path of something below a path that was moved, where joining with an empty rest would add a slash
+pub fn moved_path(to: &Path, rest: &Path) -> PathBuf {
+    if rest.as_os_str().is_empty() {
+        to.into()
+    } else {
+        to.join(rest)
+    }
+}
+
+/// Find every file in the directory that isn't ignored
+fn files(dir: &Path, ignores: &Ignores) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() && !ignores.is_ignored(&path, true) => dirs.push(path),
+                Ok(file_type) if file_type.is_file() && !ignores.is_ignored(&path, false) => files.push(path),
+                _ => {},
+            }
+        }
+    }
+    files
+}
+
+fn hash_file(path: &Path) -> Option<Hash> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_SIZE {
+        return None;
+    }
+    let contents = std::fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&contents);
+    let contents_hash = hasher.finish();
+    if path.extension().is_none_or(|ext| ext != "rs") {
+        return Some(Hash {
+            contents: contents_hash,
+            code: contents_hash,
+        });
+    }
+
+    // Leave out the lines with doc comments, so a change to nothing but those gives the same hash
+    let mut hasher = DefaultHasher::new();
+    for line in contents.split(|&byte| byte == b'\n') {
+        let trimmed = line.trim_ascii_start();
+        if !trimmed.starts_with(b"///") && !trimmed.starts_with(b"//!") {
+            hasher.write(line);
+            hasher.write_u8(b'\n');
+        }
+    }
+    Some(Hash {
+        contents: contents_hash,
+        code: hasher.finish(),
+    })
+}
@@ -0,0 +1,156 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// The names of the files with ignore rules, in the order they are read so the rules in the last one win
+const FILE_NAMES: &[&str] = &[".gitignore", ".ignore"];
+
+/// Temporary files, swap files and backups from common editors, these are ignored unless the config file says otherwise
+pub const EDITOR_PATTERNS: &[&str] = &[
+    // Vim swap files, and the file it writes to check if a directory is writable
+    "*.swp",
+    "*.swo",
+    "*.swx",
+    "4913",
+    // Backups from Emacs, Vim and others
+    "*~",
+    // Emacs auto-save files and locks
+    "#*#",
+    ".#*",
+    // JetBrains safe writes
+    "*___jb_tmp___",
+    "*___jb_old___",
+    // Kate swap files
+    ".*.kate-swp",
+];
+
+/// The rules for which paths in a crate directory never trigger a run
+///
+/// The rules from the config file are fixed, while the ignore files are read again when they change. Like for git
+/// and ripgrep, the ignore files can be in any directory and apply to everything below it, with the rules in the
+/// deeper directories taking precedence. The rules that git keeps outside the tree are used as well, with the lowest
+/// precedence.
+pub struct Ignores {
+    crate_dir: PathBuf,
+    /// .git and the patterns from the config file, these take precedence over the ignore files
+    config: Gitignore,
+    /// The rules from the ignore files in each directory that has any, parent directories before their children
+    files: Vec<Gitignore>,
+    /// The rules from .git/info/exclude and the global excludes file from the git config, in that order
+    git: Vec<Gitignore>,
+}
+
+impl Ignores {
+    pub fn new(crate_dir: &Path, config: Gitignore) -> Ignores {
+        let mut ignores = Ignores {
+            crate_dir: crate_dir.into(),
+            config,
+            files: Vec::new(),
+            git: Vec::new(),
+        };
+        ignores.reload();
+        ignores
+    }
+
+    /// Read the ignore files again, looking for them in every directory that isn't ignored
+    pub fn reload(&mut self) {
+        self.files.clear();
+        self.git = read_git_excludes(&self.crate_dir);
+
+        let mut dirs = vec![self.crate_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            if let Some(files) = read_ignore_files(&dir) {
+                self.files.push(files);
+            }
+
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut children: Vec<PathBuf> = entries
+                .flatten()
+                .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+                .map(|entry| entry.path())
+                .filter(|path| !self.is_ignored(path, true))
+                .collect();
+            // Visit them in order, keeping every parent before its children in the list of files
+            children.sort_unstable_by(|a, b| b.cmp(a));
+            dirs.extend(children);
+        }
+    }
+
+    /// Check if a path in the crate directory is ignored, paths outside it never are
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !path.starts_with(&self.crate_dir) {
+            return false;
+        }
+        std::iter::once(&self.config)
+            .chain(self.files.iter().rev().filter(|files| path.starts_with(files.path())))
+            .chain(self.git.iter())
+            .map(|rules| rules.matched_path_or_any_parents(path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+
+    /// Check if a path is an ignore file, so the rules have to be read again when it changes
+    pub fn is_ignore_file(path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| FILE_NAMES.contains(&name))
+    }
+}
+
+/// Read the ignore files in a directory, if there are any
+fn read_ignore_files(dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for name in FILE_NAMES {
+        let path = dir.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        found = true;
+        // The error may only be for some of the lines, so the rest of the file is still used
+        if let Some(e) = builder.add(&path) {
+            log::warn!("Failed to read {}: {}", path.to_string_lossy(), e);
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    match builder.build() {
+        Ok(rules) => Some(rules),
+        Err(e) => {
+            log::warn!("Failed to load the ignore files in {}: {}", dir.to_string_lossy(), e);
+            None
+        },
+    }
+}
+
+/// Read the excludes that git keeps outside the tree, from the repository the directory is in and the git config
+fn read_git_excludes(dir: &Path) -> Vec<Gitignore> {
+    let mut excludes = Vec::new();
+
+    // Anchored patterns in both are relative to the root of the repository
+    let repo_dir = dir.ancestors().find(|dir| dir.join(".git").is_dir()).unwrap_or(dir);
+
+    let exclude = repo_dir.join(".git").join("info").join("exclude");
+    if exclude.is_file() {
+        let mut builder = GitignoreBuilder::new(repo_dir);
+        if let Some(e) = builder.add(&exclude) {
+            log::warn!("Failed to read {}: {}", exclude.to_string_lossy(), e);
+        }
+        match builder.build() {
+            Ok(rules) => excludes.push(rules),
+            Err(e) => log::warn!("Failed to load {}: {}", exclude.to_string_lossy(), e),
+        }
+    }
+
+    let (global, e) = GitignoreBuilder::new(repo_dir).build_global();
+    if let Some(e) = e {
+        log::warn!("Failed to read the global excludes file from the git config: {}", e);
+    }
+    excludes.push(global);
+
+    excludes
+}
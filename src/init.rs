@@ -0,0 +1,133 @@
+use crate::config::{Config, FILE_NAMES};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Patterns for files that often change without affecting the build
+const COMMON_IGNORES: &[&str] = &[".idea/", ".vscode/", "*.log"];
+
+/// Find the members of the workspace, if the crate directory is a workspace
+fn workspace_members(crate_dir: &Path) -> Vec<String> {
+    let manifest = match std::fs::read_to_string(crate_dir.join("Cargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(_) => return Vec::new(),
+    };
+    let manifest: toml::Value = match toml::from_str(&manifest) {
+        Ok(manifest) => manifest,
+        Err(_) => return Vec::new(),
+    };
+
+    manifest
+        .get("workspace")
+        .and_then(|workspace| workspace.get("members"))
+        .and_then(|members| members.as_array())
+        .map(|members| {
+            members
+                .iter()
+                .filter_map(|member| member.as_str())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Generate the contents of the starter config file
+fn starter_config(crate_dir: &Path) -> String {
+    let mut out = String::new();
+
+    out.push_str("# Configuration for auto-check-rs\n");
+    out.push_str("#\n");
+    out.push_str("# Options given on the command line override the ones in this file, and the file is\n");
+    out.push_str("# reloaded automatically when it changes.\n\n");
+
+    out.push_str("# Verbosity level, the same as the number of -v flags\n");
+    out.push_str("# verbose = 2\n\n");
+
+    out.push_str("# Delay in milliseconds before triggering\n");
+    out.push_str("delay = 1000\n\n");
+
+    out.push_str("# Don't run once after startup, wait for a change\n");
+    out.push_str("# run-first = false\n\n");
+
+    out.push_str("# The temporary files from editors that never trigger a run, this replaces the built-in list\n");
+    out.push_str("# editor-ignore = [\"*.swp\", \"*~\"]\n\n");
+
+    out.push_str("# Paths that never trigger a run, in addition to the ones in .gitignore and .ignore\n");
+    out.push_str("ignore = [\n");
+    for pattern in COMMON_IGNORES {
+        writeln!(out, "    {:?},", pattern).unwrap();
+    }
+    out.push_str("]\n\n");
+
+    out.push_str("# The commands to run in order, stopping at the first failure. These are the built-in\n");
+    out.push_str("# defaults, and setting this replaces them and the options for them below. A command can\n");
+    out.push_str("# either be a list of arguments, a line that is split like a shell would, or a table like\n");
+    out.push_str("# { name = \"demo\", cmd = \"cargo run --example demo\" }\n");
+    out.push_str("# commands = [\n");
+    out.push_str("#     [\"cargo\", \"check\"],\n");
+    out.push_str("#     [\"cargo\", \"clippy\", \"--all-targets\"],\n");
+    out.push_str("#     [\"cargo\", \"test\"],\n");
+    out.push_str("# ]\n\n");
+
+    out.push_str("# Commands to run when Cargo.lock, a Cargo.toml or deny.toml changes, instead of the ones\n");
+    out.push_str("# above if nothing else changed\n");
+    out.push_str("# lockfile-commands = [\"cargo audit\", \"cargo deny check\"]\n\n");
+
+    out.push_str("# Commands to run after the ones above, which don't change the result when they fail\n");
+    out.push_str("# on-success = [\"paplay /usr/share/sounds/freedesktop/stereo/complete.oga\"]\n");
+    out.push_str("# on-failure = [\"notify-send 'auto-check-rs' 'The checks failed'\"]\n\n");
+
+    out.push_str("# Commands to run every so often, whether anything changed or not\n");
+    out.push_str("# schedule = [{ cmd = \"cargo audit\", every = \"24h\" }]\n\n");
+
+    out.push_str("# Features for the built-in check, build, clippy, test and doc commands\n");
+    out.push_str("# features = [\"foo\", \"bar\"]\n");
+    out.push_str("# all-features = true\n");
+    out.push_str("# no-default-features = true\n\n");
+
+    out.push_str("# Only run or skip commands by name\n");
+    out.push_str("# only = [\"test\"]\n");
+    out.push_str("# skip = [\"clippy\"]\n\n");
+
+    out.push_str("# Extra environment variables for commands by name\n");
+    out.push_str("# [command-env.check]\n");
+    out.push_str("# RUSTFLAGS = \"-D warnings\"\n\n");
+
+    out.push_str("# Other commands for changes below a path, picked by name from the ones above with only\n");
+    out.push_str("# or added with commands\n");
+    out.push_str("# [[routes]]\n");
+    out.push_str("# path = \"docs\"\n");
+    out.push_str("# commands = [\"mdbook build\"]\n\n");
+
+    out.push_str("# Profiles are selected with --profile and override the settings above\n");
+    out.push_str("[profiles.fast]\n");
+    out.push_str("commands = [[\"cargo\", \"check\"]]\n");
+
+    let members = workspace_members(crate_dir);
+    if !members.is_empty() {
+        out.push_str("\n# The members of the workspace\n");
+        for member in members.iter() {
+            writeln!(out, "#   {}", member).unwrap();
+        }
+        out.push_str("#\n# A profile that only checks a single member could look like this:\n");
+        out.push_str("# [profiles.member]\n");
+        out.push_str("# commands = [\"cargo check -p <member>\", \"cargo test -p <member>\"]\n");
+    }
+
+    out
+}
+
+/// Write a starter config file to the crate directory
+pub fn run(crate_dir: &Path, force: bool) -> Result<PathBuf, String> {
+    let existing = Config::find(crate_dir);
+    if let (Some(existing), false) = (&existing, force) {
+        return Err(format!(
+            "{} already exists, use --force to overwrite it",
+            existing.to_string_lossy()
+        ));
+    }
+
+    let path = existing.unwrap_or_else(|| crate_dir.join(FILE_NAMES[0]));
+    std::fs::write(&path, starter_config(crate_dir))
+        .map_err(|e| format!("Failed to write {}: {}", path.to_string_lossy(), e))?;
+    Ok(path)
+}
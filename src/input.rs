@@ -0,0 +1,46 @@
+use crate::watcher::DebouncedEvent;
+use std::io::{BufRead, IsTerminal};
+use std::sync::mpsc::Sender;
+
+/// Something typed in the terminal, followed by Enter
+#[derive(Debug)]
+pub enum Input {
+    /// Just Enter, to run the commands right away
+    Run,
+    /// `q`, to stop watching
+    Quit,
+}
+
+/// Read lines from stdin in the background and send what they ask for to the main loop
+///
+/// Nothing is read unless stdin is a terminal, since it may be meant for the commands when it is piped.
+pub fn spawn(tx: Sender<DebouncedEvent>) {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        log::debug!("Not reading stdin since it isn't a terminal");
+        return;
+    }
+    log::info!("Press Enter to run the commands, or q and Enter to quit");
+    std::thread::spawn(move || {
+        for line in stdin.lock().lines() {
+            let input = match line {
+                Ok(line) => match line.trim() {
+                    "" => Input::Run,
+                    "q" | "quit" => Input::Quit,
+                    other => {
+                        log::warn!("Unknown input {:?}, press Enter to run the commands or q and Enter to quit", other);
+                        continue;
+                    },
+                },
+                Err(e) => {
+                    log::debug!("Failed to read stdin: {}", e);
+                    return;
+                },
+            };
+            if tx.send(DebouncedEvent::Input(input)).is_err() {
+                return;
+            }
+        }
+        log::debug!("Stopped reading stdin after it was closed");
+    });
+}
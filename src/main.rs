@@ -1,269 +1,384 @@
 #![deny(warnings)]
-#![cfg_attr(feature = "cargo-clippy", deny(clippy::all))]
+#![deny(clippy::all)]
 
-extern crate notify;
 extern crate ignore;
 
-use std::collections::BTreeSet;
+mod cargo;
+mod changes;
+mod cli;
+mod command;
+mod config;
+mod deny;
+mod git;
+mod hashes;
+mod ignores;
+mod init;
+mod input;
+mod netfs;
+mod nextest;
+mod pipeline;
+mod project;
+mod remote;
+mod routes;
+mod runner;
+mod settings;
+mod signals;
+mod watcher;
+
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use notify::Watcher;
-use ignore::{
-    Match,
-    gitignore::{Gitignore, GitignoreBuilder},
-};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use clap::Parser;
+use cli::Args;
+use changes::Trigger;
+use command::RunInfo;
+use pipeline::Pipeline;
+use project::Project;
+use settings::{Backend, Color, Settings};
+use watcher::Watcher;
+
+const WATCH_LIMIT_HELP: &str = "\
+The limit for the number of inotify watches was reached, falling back to polling for changes.
+To raise the limit run:
+    sudo sysctl fs.inotify.max_user_watches=524288
+and add fs.inotify.max_user_watches=524288 to /etc/sysctl.conf to keep it after a reboot.";
+
+/// The interval used when falling back to polling
+const FALLBACK_POLL: Duration = Duration::from_millis(1000);
 
-const USAGE: &str = "auto-check-rs
+fn main() {
+    //std::env::set_var("RUST_BACKTRACE", "1");
 
-Usage:
-    auto-check-rs [options] [-vvvv] <crate-dir>
-    auto-check-rs (-h | --help)
-    auto-check-rs --version
+    let mut args = Args::parse();
 
-Options:
-    -h --help                       Show this screen.
-    --version                       Show version.
-    -v --verbose                    Increase the verbosity level, default is only errors
-    --delay=MS                      Delay in milliseconds before triggering [default: 1000]
-    -c --custom-cmd=CMD             Run the specified command without arguments after the other checks
-    --no-run-first                  Don't always run once after startup, wait for a change
-    --no-check                      Don't run cargo check
-    --no-clippy                     Don't run cargo clippy
-    --no-test                       Don't run cargo test
-";
+    if let Some(command) = args.command.take() {
+        command.run();
+        return;
+    }
 
-enum Action {
-    Nothing,
-    Custom(String),
-    FilesChanged(Vec<PathBuf>),
-}
+    signals::handle();
 
-struct Changes {
-    base_dir: PathBuf,
-    gitignore: Gitignore,
-    ignore_changes: Arc<AtomicBool>,
-    custom: Option<String>,
-    changed: BTreeSet<PathBuf>,
-}
-
-impl Changes {
-    fn new<P: Into<PathBuf>>(base_dir: P, gitignore: Gitignore) -> Changes {
-        let base_dir = base_dir.into();
-        assert!(base_dir.is_absolute());
-        Changes {
-            base_dir,
-            gitignore,
-            ignore_changes: Default::default(),
-            custom: None,
-            changed: Default::default(),
+    let current_dir = std::env::current_dir().expect("Failed to get the current directory");
+    if let Some(manifest_path) = &args.manifest_path {
+        let manifest_path = current_dir.join(manifest_path);
+        if manifest_path.file_name().is_none_or(|name| name != "Cargo.toml") || !manifest_path.is_file() {
+            eprintln!("The manifest path must be a path to a Cargo.toml file: {}", manifest_path.to_string_lossy());
+            std::process::exit(1);
         }
+        let crate_dir = manifest_path.parent().expect("A file always has a parent directory");
+        args.crate_dirs.push(crate_dir.into());
+    } else if args.crate_dirs.is_empty() {
+        let crate_dir = cargo::find_manifest_dir(&current_dir).unwrap_or_else(|| current_dir.clone());
+        args.crate_dirs.push(crate_dir);
     }
 
-    fn add_custom<T: Into<String>>(&mut self, reason: T) {
-        self.custom = Some(reason.into());
-    }
+    let all_settings: Vec<_> = args
+        .crate_dirs
+        .iter()
+        .map(|crate_dir| {
+            // The directories are made canonical since all the paths from the watcher are
+            let crate_dir = current_dir.join(crate_dir);
+            let crate_dir = crate_dir.canonicalize().unwrap_or(crate_dir);
+            let settings = Settings::load(&args, &crate_dir).unwrap_or_else(|e| {
+                eprintln!("{}: {}", crate_dir.to_string_lossy(), e);
+                std::process::exit(1);
+            });
+
+            // Only require a crate when there is something that runs cargo
+            let uses_cargo = settings.commands.iter().any(|cmd| cmd.args[0] == "cargo" || cmd.args[0] == "cross");
+            let checked = if uses_cargo {
+                cargo::check_crate_dir(&crate_dir)
+            } else if crate_dir.is_dir() {
+                Ok(())
+            } else {
+                Err(format!("{} is not a directory", crate_dir.to_string_lossy()))
+            };
+            if let Err(e) = checked {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
 
-    fn add<P: AsRef<Path>>(&mut self, fpath: &P) {
-        let ignore = self.ignore_changes.load(Ordering::Relaxed);
-        let fpath = fpath.as_ref();
-        match fpath.strip_prefix(&self.base_dir) {
-            Ok(fpath) => match self.gitignore.matched_path_or_any_parents(fpath, false) {
-                Match::Ignore(_) => {
-                    log::trace!("Ignoring path from .gitignore: {}", fpath.to_string_lossy());
-                },
-                Match::Whitelist(_) | Match::None => {
-                    if ignore {
-                        log::debug!("Ignored change: {}", fpath.to_string_lossy());
-                    } else {
-                        log::debug!("Detected change: {}", fpath.to_string_lossy());
-                        self.changed.insert(fpath.into());
-                    }
-                },
-            },
-            Err(_) => {
-                log::error!("Ignoring unknown path: {}", fpath.to_string_lossy());
-            },
-        }
-    }
+            (crate_dir, settings)
+        })
+        .collect();
 
-    fn take_current_action(&mut self) -> Action {
-        if let Some(reason) = self.custom.take() {
-            // Return the custom reason for running
-            self.changed = BTreeSet::new(); // Ignore any changes up until now
-            self.ignore_changes.store(true, Ordering::Relaxed);
-            Action::Custom(reason)
-        } else if !self.changed.is_empty() {
-            // Return the list of changed files
-            let mut changed = BTreeSet::new();
-            std::mem::swap(&mut changed, &mut self.changed);
-            self.ignore_changes.store(true, Ordering::Relaxed);
-            Action::FilesChanged(changed.into_iter().collect())
-        } else {
-            // There is nothing to do here
-            Action::Nothing
+    if args.print_config {
+        for (i, (crate_dir, settings)) in all_settings.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            println!("crate directory: {}", crate_dir.to_string_lossy());
+            print!("{}", settings);
         }
+        return;
     }
-}
-
-fn main() {
-    //std::env::set_var("RUST_BACKTRACE", "1");
-
-    let args = docopt::Docopt::new(USAGE)
-        .and_then(|d| d.parse())
-        .unwrap_or_else(|e| e.exit());
 
+    let verbose = all_settings.iter().map(|(_, settings)| settings.verbose).max().unwrap_or(0);
+    let color = all_settings.iter().map(|(_, settings)| settings.color).find(|&color| color != Color::Auto);
+    let color = color.unwrap_or(Color::Auto);
     env_logger::builder()
-        .filter(None, match args.get_count("--verbose") {
+        .filter(None, match verbose {
             0 => log::LevelFilter::Error,
             1 => log::LevelFilter::Warn,
             2 => log::LevelFilter::Info,
             3 => log::LevelFilter::Debug,
             _ => log::LevelFilter::Trace,
         })
+        .write_style(match color {
+            Color::Auto => env_logger::WriteStyle::Auto,
+            Color::Always => env_logger::WriteStyle::Always,
+            Color::Never => env_logger::WriteStyle::Never,
+        })
         .init();
 
-    let mut crate_dir = std::path::PathBuf::from(args.get_str("<crate-dir>"));
-
-    if crate_dir.is_relative() {
-        let mut tmp = std::env::current_dir().expect("Failed to get the current directory");
-        tmp.push(crate_dir);
-        crate_dir = tmp;
+    for (crate_dir, settings) in all_settings.iter() {
         log::debug!("Using crate directory: {}", crate_dir.to_string_lossy());
+        if let Some(path) = &settings.config_path {
+            log::debug!("Using config file: {}", path.to_string_lossy());
+        }
     }
 
-    let gitignore = {
-        let mut builder = GitignoreBuilder::new(&crate_dir);
-        // The .git directory is currently not ignored, and
-        // there is no way of initializing it like git would yet.
-        // See: https://github.com/BurntSushi/ripgrep/issues/1040
-        builder
-            .add_line(None, "**/.git")
-            .expect("Failed to add .git to ignore list");
-        builder.add(".gitignore");
-        builder.build().expect("Failed to load .gitignore")
-    };
-
-    let mut commands_to_run: Vec<Vec<String>> = Vec::new();
-
-    if !args.get_bool("--no-check") {
-        commands_to_run.push(vec!["cargo".into(), "check".into()]);
-    }
-
-    if !args.get_bool("--no-clippy") {
-        commands_to_run.push(vec![
-            "cargo".into(),
-            "clippy".into(),
-            "--all-targets".into(),
-            "--all-features".into(),
-        ]);
+    if all_settings.iter().any(|(_, settings)| settings.once) {
+        let mut success = true;
+        for (crate_dir, settings) in all_settings.iter() {
+            // Nothing changed, so only the crate directory has a value
+            let info = RunInfo {
+                trigger: Trigger::Initial,
+                changed_files: Vec::new(),
+                changed_crates: Vec::new(),
+                crate_dir: crate_dir.to_string_lossy().into(),
+            };
+            let pipeline = Pipeline::new(settings);
+            if !pipeline.sync(crate_dir) {
+                success = false;
+                continue;
+            }
+            let mut commands = pipeline.commands.clone();
+            info.apply(&mut commands);
+            let run = runner::run_tiers(&commands, crate_dir, None, pipeline.keep_going, None);
+            let mut hooks = pipeline.hooks(&run);
+            info.apply(&mut hooks);
+            runner::run_hooks(&hooks, crate_dir);
+            signals::record(crate_dir, &run);
+            success &= run.passed();
+            println!();
+        }
+        std::process::exit(if success { 0 } else { 1 });
     }
 
-    if !args.get_bool("--no-test") {
-        commands_to_run.push(vec!["cargo".into(), "test".into()]);
-    }
+    let (inotify_tx, inotify_rx) = std::sync::mpsc::channel();
 
-    let custom_cmd = args.get_str("--custom-cmd");
-    if !custom_cmd.is_empty() {
-        commands_to_run.push(vec![custom_cmd.into()]);
+    // There is only one watcher, so polling is used for every crate directory if any of them asks for it
+    let mut poll = all_settings.iter().filter_map(|(_, settings)| settings.poll).min();
+    let backend = all_settings
+        .iter()
+        .map(|(_, settings)| settings.backend)
+        .find(|backend| *backend != Backend::Auto)
+        .unwrap_or(Backend::Auto);
+    // The native events are often missing on network file systems, unless a backend was asked for anyway
+    if poll.is_none() && backend == Backend::Auto {
+        let network = all_settings.iter().find_map(|(crate_dir, settings)| {
+            std::iter::once(crate_dir)
+                .chain(settings.watch.iter())
+                .find_map(|path| netfs::network_fs(path).map(|fs_type| (path, fs_type)))
+        });
+        if let Some((path, fs_type)) = network {
+            eprintln!(
+                "{} is on a {} file system where changes are often missed, polling for changes instead",
+                path.to_string_lossy(),
+                fs_type
+            );
+            poll = Some(FALLBACK_POLL);
+        }
     }
-
-    if commands_to_run.is_empty() {
-        log::error!("Cowardly refusing to start because there is no commands to run");
-        std::process::exit(1);
+    if let Some(poll) = poll {
+        log::debug!("Polling for changes every {} ms", poll.as_millis());
     }
-
-    let delay_ms: u64 = args
-        .get_str("--delay")
-        .parse()
-        .expect("Expected positive number for --delay");
-    let delay = std::time::Duration::from_millis(delay_ms);
-
-    let (inotify_tx, inotify_rx) = std::sync::mpsc::channel();
-    let (action_tx, action_rx) = std::sync::mpsc::channel::<Action>();
-
-    let mut watcher = notify::watcher(inotify_tx, std::time::Duration::from_millis(100))
-        .expect("Failed to initialize inotify watcher");
-    watcher
-        .watch(&crate_dir, notify::RecursiveMode::Recursive)
-        .expect("Failed to add watch");
-
-    let mut changes = Changes::new(&crate_dir, gitignore);
-    let ignore_changes = changes.ignore_changes.clone();
-
-    std::thread::spawn(move || {
-        for action in action_rx.iter() {
-            let run_commands = match action {
-                Action::Nothing => {
-                    log::trace!("No changes detected");
-                    false
-                },
-                Action::Custom(reason) => {
-                    log::info!("{}", reason);
-                    true
-                },
-                Action::FilesChanged(current_paths) => {
-                    log::info!("Detected change: {:?}", current_paths);
-                    true
-                },
-            };
-
-            if run_commands {
-                'command_loop: for cmd in commands_to_run.iter() {
-                    println!();
-                    log::info!("Running command {:?}", cmd);
-                    let mut command = std::process::Command::new(&cmd[0]);
-                    command.current_dir(&crate_dir);
-                    command.args(&cmd[1..]);
-
-                    match command.status() {
-                        Ok(status) => {
-                            if status.success() {
-                                log::debug!("Successfully executed {:?}", command);
-                            } else {
-                                log::error!("Failed to execute {:?}: Returned status {:?}", command, status.code());
-                                break 'command_loop;
-                            }
-                        },
-                        Err(e) => {
-                            log::error!("Failed to execute {:?}: {:?}", command, e);
-                            break 'command_loop;
-                        },
-                    }
-                }
-                println!();
-                ignore_changes.store(false, Ordering::Relaxed);
+    let follow_symlinks = all_settings.iter().any(|(_, settings)| settings.follow_symlinks);
+    let mut projects: Vec<Project> = all_settings
+        .into_iter()
+        .map(|(crate_dir, settings)| Project::start(crate_dir, settings))
+        .collect();
+
+    let mut watcher = start_watcher(&inotify_tx, backend, poll, follow_symlinks, &projects);
+    input::spawn(inotify_tx.clone());
+
+    // A path in a crate directory belongs to the innermost project, and any project can also watch paths outside it
+    let add = |projects: &mut [Project], fpath: &Path| {
+        let index = project::find(projects, fpath);
+        let mut found = false;
+        let mut reloaded = false;
+        for (i, project) in projects.iter_mut().enumerate() {
+            if index == Some(i) || project.is_watched(fpath) {
+                reloaded |= project.add(fpath, &args);
+                found = true;
             }
         }
-    });
-
-    if !args.get_bool("--no-run-first") {
-        changes.add_custom("Initial check");
-    }
+        // The directories of single watched files have other files in them as well
+        if !found {
+            log::trace!("Ignoring unknown path: {}", fpath.to_string_lossy());
+        }
+        reloaded
+    };
 
     loop {
-        use notify::DebouncedEvent::*;
+        use watcher::DebouncedEvent::*;
         use std::sync::mpsc::RecvTimeoutError::*;
 
-        match inotify_rx.recv_timeout(delay) {
-            Ok(NoticeWrite(_)) => {},
-            Ok(NoticeRemove(_)) => {},
-            Ok(Chmod(_)) => {},
-            Ok(Create(fpath)) => changes.add(&fpath),
-            Ok(Write(fpath)) => changes.add(&fpath),
-            Ok(Remove(fpath)) => changes.add(&fpath),
+        // Run the projects that have waited long enough for their changes, and wait for the next one to be ready
+        let now = Instant::now();
+        let mut rewatch = false;
+        for project in projects.iter_mut() {
+            if project.deadline().is_some_and(|deadline| deadline <= now) {
+                rewatch |= project.publish();
+            }
+        }
+        let timeout = match projects.iter().filter_map(|project| project.deadline()).min() {
+            Some(deadline) => deadline.saturating_duration_since(now),
+            None => Duration::from_secs(3600),
+        };
+
+        let reloaded = match inotify_rx.recv_timeout(timeout) {
+            Ok(Create(fpath)) => {
+                // Files created in a new directory before it was watched are only found by looking
+                let mut reloaded = false;
+                if fpath.is_dir() {
+                    for file in watch_new_path(&mut watcher, &inotify_tx, &projects, &fpath) {
+                        reloaded |= add(&mut projects, &file);
+                    }
+                }
+                add(&mut projects, &fpath) | reloaded
+            },
+            Ok(Write(fpath)) => add(&mut projects, &fpath),
+            Ok(Remove(fpath)) => {
+                watcher.unwatch_tree(&fpath);
+                add(&mut projects, &fpath)
+            },
             Ok(Rename(spath, dpath)) => {
-                changes.add(&spath);
-                changes.add(&dpath);
+                // The watches for a moved directory would keep reporting the old paths
+                watcher.unwatch_tree(&spath);
+                let mut pending = false;
+                for project in projects.iter_mut() {
+                    pending |= project.rename(&spath, &dpath);
+                }
+                let mut reloaded = false;
+                if dpath.is_dir() {
+                    for file in watch_new_path(&mut watcher, &inotify_tx, &projects, &dpath) {
+                        reloaded |= add(&mut projects, &file);
+                    }
+                }
+                // A temporary file that was moved into place is only a change to the new path
+                let source = !pending && add(&mut projects, &spath);
+                source | add(&mut projects, &dpath) | reloaded
             },
-            Ok(Rescan) => log::warn!("Some issue detected, rescanning all watches"),
-            Ok(Error(e, fpath)) => log::error!("{:?} ({:?})", e, fpath),
-            Err(Timeout) => {
-                action_tx
-                    .send(changes.take_current_action())
-                    .expect("Failed to publish action");
+            Ok(Metadata(fpath)) => {
+                // Touching a file only starts a run for the trigger file, since the contents stay the same
+                for project in projects.iter_mut() {
+                    project.trigger(&fpath);
+                }
+                false
             },
+            Ok(Rescan) => {
+                // Directories may have been created without being watched, and files changed without being seen
+                log::warn!("Events may have been lost, checking everything again");
+                for project in projects.iter_mut() {
+                    project.resync();
+                }
+                true
+            },
+            Ok(Input(input::Input::Run)) => {
+                for project in projects.iter_mut() {
+                    project.run_now("Triggered from the terminal");
+                }
+                false
+            },
+            Ok(Input(input::Input::Quit)) => {
+                log::info!("Quitting");
+                signals::quit(0);
+            },
+            Ok(Error(e)) => {
+                log::error!("{:?} ({:?})", e.kind, e.paths);
+                false
+            },
+            Err(Timeout) => false,
             Err(e) => panic!("inotify channel died: {:?}", e),
+        };
+
+        // The config file or the build scripts may have added paths to watch, directories may no longer be ignored,
+        // and after lost events anything could be missing
+        if reloaded || rewatch {
+            for project in projects.iter() {
+                watch_new_path(&mut watcher, &inotify_tx, &projects, &project.crate_dir);
+                for path in project.watch_paths() {
+                    watch_new_path(&mut watcher, &inotify_tx, &projects, path);
+                }
+            }
         }
     }
 }
+
+/// Watch a single file, or a directory and everything below it that isn't ignored by the project it belongs to
+fn watch_tree(watcher: &mut Watcher, projects: &[Project], path: &Path) -> notify::Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return watcher.watch_file(path).map(|()| Vec::new());
+    }
+    watcher.watch_tree(path, |path| match project::find(projects, path) {
+        Some(index) => projects[index].is_ignored_dir(path),
+        None => !projects.iter().any(|project| project.is_watched(path)),
+    })
+}
+
+/// Start watching all the crate directories, falling back to polling if the limit for native watches is reached
+fn start_watcher(
+    tx: &Sender<watcher::DebouncedEvent>,
+    backend: Backend,
+    poll: Option<Duration>,
+    follow_symlinks: bool,
+    projects: &[Project],
+) -> Watcher {
+    let watcher = Watcher::new(tx.clone(), Duration::from_millis(100), backend, poll).and_then(|mut watcher| {
+        watcher.follow_symlinks = follow_symlinks;
+        for project in projects.iter() {
+            watch_tree(&mut watcher, projects, &project.crate_dir)?;
+            for path in project.watch_paths() {
+                watch_tree(&mut watcher, projects, path)?;
+            }
+        }
+        Ok(watcher)
+    });
+
+    match watcher {
+        Ok(watcher) => watcher,
+        Err(e) if poll.is_none() && matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+            eprintln!("{}", WATCH_LIMIT_HELP);
+            start_watcher(tx, Backend::Poll, Some(FALLBACK_POLL), follow_symlinks, projects)
+        },
+        Err(e) => {
+            eprintln!("Failed to watch for changes: {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Watch a path that appeared while running, restarting the watcher with polling if the limit is reached
+///
+/// Returns the files that are already in the new directories.
+fn watch_new_path(
+    watcher: &mut Watcher,
+    tx: &Sender<watcher::DebouncedEvent>,
+    projects: &[Project],
+    dir: &Path,
+) -> Vec<PathBuf> {
+    match watch_tree(watcher, projects, dir) {
+        Ok(files) => files,
+        Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+            eprintln!("{}", WATCH_LIMIT_HELP);
+            *watcher = start_watcher(tx, Backend::Poll, Some(FALLBACK_POLL), watcher.follow_symlinks, projects);
+            Vec::new()
+        },
+        Err(e) => {
+            log::error!("Failed to watch {}: {}", dir.to_string_lossy(), e);
+            Vec::new()
+        },
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+/// The file systems where the native events are often missing, so changes have to be polled for
+const NETWORK_FILE_SYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "afpfs",
+    "webdav",
+    "9p",
+    "virtiofs",
+    "vboxsf",
+    "drvfs",
+    "fuse.sshfs",
+];
+
+/// Find the type of the file system the path is on, if it is one where the native events can't be trusted
+pub fn network_fs(path: &Path) -> Option<String> {
+    fs_type(path).filter(|fs_type| NETWORK_FILE_SYSTEMS.contains(&fs_type.as_str()))
+}
+
+/// Look up the file system in the mount table, using the mount point with the longest path the path is below
+#[cfg(target_os = "linux")]
+fn fs_type(path: &Path) -> Option<String> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let mount_point = unescape(fields.nth(1)?);
+            let fs_type = fields.next()?;
+            Some((mount_point, fs_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| Path::new(mount_point).components().count())
+        .map(|(_, fs_type)| fs_type.into())
+}
+
+/// Decode the octal escapes the kernel uses for spaces and other special characters in the mount table
+#[cfg(target_os = "linux")]
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let mut bytes = field.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'\\' {
+            let digits: Vec<u8> = bytes.clone().take(3).collect();
+            if let Some(value) = std::str::from_utf8(&digits).ok().and_then(|s| u8::from_str_radix(s, 8).ok()) {
+                out.push(value);
+                bytes.nth(2);
+                continue;
+            }
+        }
+        out.push(b);
+    }
+    String::from_utf8_lossy(&out).into()
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+fn fs_type(path: &Path) -> Option<String> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    Some(name.to_string_lossy().into())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+fn fs_type(_path: &Path) -> Option<String> {
+    None
+}
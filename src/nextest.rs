@@ -0,0 +1,106 @@
+use serde::Deserialize;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// The exit code of cargo nextest when the tests ran and some of them failed
+pub const TESTS_FAILED: i32 = 100;
+
+/// Check if cargo nextest is installed, so the tests can run with it
+pub fn installed() -> bool {
+    std::process::Command::new("cargo")
+        .args(["nextest", "--version"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// How a test that ran with cargo nextest went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+/// A test that finished, from the libtest JSON that cargo nextest writes with `--message-format libtest-json`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    /// The binary and the test, like `core::bin/cli parser::tests::parses`
+    pub name: String,
+    pub outcome: Outcome,
+    /// How long the test ran, which ignored tests don't have
+    pub time: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+struct JsonEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    name: Option<String>,
+    exec_time: Option<f64>,
+}
+
+/// Parse a line of output from cargo nextest as a JSON event, returns the test that finished if it is about one
+///
+/// Returns None for anything that isn't an event, and Some(None) for the other events, like a test that started.
+pub fn parse_event(line: &str) -> Option<Option<TestResult>> {
+    if !line.starts_with("{\"type\":") {
+        return None;
+    }
+    let event: JsonEvent = serde_json::from_str(line).ok()?;
+    let outcome = match (event.kind.as_str(), event.event.as_str()) {
+        ("test", "ok") => Outcome::Passed,
+        ("test", "failed") => Outcome::Failed,
+        ("test", "ignored") => Outcome::Ignored,
+        _ => return Some(None),
+    };
+    let time = event.exec_time.filter(|secs| secs.is_finite() && *secs >= 0.0).map(Duration::from_secs_f64);
+    Some(event.name.map(|name| TestResult {
+        // The binary is separated from the test by a $, which nextest shows as a space itself
+        name: name.replacen('$', " ", 1),
+        outcome,
+        time,
+    }))
+}
+
+/// Sum up the tests that ran, like `12 tests: 10 passed, 1 failed and 1 ignored`, or None if none ran
+pub fn summarize(tests: &[TestResult]) -> Option<String> {
+    if tests.is_empty() {
+        return None;
+    }
+    let count = |outcome: Outcome| tests.iter().filter(|test| test.outcome == outcome).count();
+    let counts: Vec<String> = [("passed", Outcome::Passed), ("failed", Outcome::Failed), ("ignored", Outcome::Ignored)]
+        .iter()
+        .map(|(name, outcome)| (name, count(*outcome)))
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| format!("{} {}", count, name))
+        .collect();
+    let counts = match counts.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => counts.join(""),
+    };
+    let plural = if tests.len() == 1 { "test" } else { "tests" };
+    Some(format!("{} {}: {}", tests.len(), plural, counts))
+}
+
+/// The tests that failed, with how long they ran, like `core::bin/cli tests::parses (12.3ms)`
+pub fn failed(tests: &[TestResult]) -> Vec<String> {
+    tests.iter().filter(|test| test.outcome == Outcome::Failed).map(describe).collect()
+}
+
+/// The tests that ran the longest, slowest first
+pub fn slowest(tests: &[TestResult], count: usize) -> Vec<String> {
+    let mut timed: Vec<&TestResult> = tests.iter().filter(|test| test.time.is_some()).collect();
+    timed.sort_by_key(|test| std::cmp::Reverse(test.time));
+    timed.into_iter().take(count).map(describe).collect()
+}
+
+fn describe(test: &TestResult) -> String {
+    match test.time {
+        Some(time) => format!("{} ({:.1?})", test.name, time),
+        None => test.name.clone(),
+    }
+}
@@ -0,0 +1,241 @@
+use crate::cargo;
+use crate::changes::Action;
+use crate::command::Command;
+use crate::remote::Ssh;
+use crate::routes::{self, Route};
+use crate::runner::Run;
+use crate::settings::Settings;
+use ignore::overrides::Override;
+use std::path::{Path, PathBuf};
+
+/// The commands a project runs and how, which is replaced when the config file is reloaded
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    /// The commands to run when Cargo.lock changes
+    pub lockfile_commands: Vec<Command>,
+    /// Other commands to run for changes below some paths
+    pub routes: Vec<Route>,
+    /// Run the rest of the commands after one of them fails
+    pub keep_going: bool,
+    /// The target directory the commands build in instead of the one of the workspace, if they have their own
+    pub isolated_target_dir: Option<PathBuf>,
+    /// Wait for other cargo processes to release their locks before running the commands
+    pub wait_for_lock: bool,
+    /// The commands run on another machine over SSH
+    pub ssh: Option<Ssh>,
+    /// The commands to run after all the commands passed
+    pub on_success: Vec<Command>,
+    /// The commands to run after any of the commands failed
+    pub on_failure: Vec<Command>,
+    /// After a failed run, only run the commands that failed until they pass
+    pub failed_first: bool,
+    /// The commands to run every so often, whether anything changed or not
+    pub schedule: Vec<Command>,
+    /// Only build the packages the changed files belong to and the ones that depend on them
+    pub affected_only: bool,
+    /// Only run the tests in the changed test files when nothing else changed
+    pub targeted_tests: bool,
+    /// Only run the doc tests when nothing but doc comments changed
+    pub doc_tests: bool,
+    /// Run all the tests after this many targeted runs, or never if 0
+    pub full_test_every: u32,
+    /// Changes to paths matching these globs can't affect the build or the tests, so they don't run anything
+    pub no_op: Override,
+}
+
+impl Pipeline {
+    pub fn new(settings: &Settings) -> Pipeline {
+        Pipeline {
+            commands: settings.commands.clone(),
+            lockfile_commands: settings.lockfile_commands.clone(),
+            routes: settings.routes.clone(),
+            keep_going: settings.keep_going,
+            failed_first: settings.failed_first,
+            isolated_target_dir: settings.isolated_target_dir.clone(),
+            wait_for_lock: settings.wait_for_lock,
+            ssh: settings.ssh.clone(),
+            on_success: settings.on_success.clone(),
+            on_failure: settings.on_failure.clone(),
+            schedule: settings.schedule.clone(),
+            no_op: settings.no_op.clone(),
+            affected_only: settings.affected_only,
+            targeted_tests: settings.targeted_tests,
+            doc_tests: settings.doc_tests,
+            full_test_every: settings.full_test_every,
+        }
+    }
+
+    /// Pick the commands to run for the action
+    ///
+    /// Changed files are routed to their commands, and anything else runs the normal commands. The commands for the
+    /// lock file run after them when the lock file, a manifest or the config of cargo deny changed, and the normal
+    /// commands are skipped if there are commands for the lock file and nothing else changed. A scheduled run only runs
+    /// the scheduled commands that are due. Paths matching the no-op globs are left out before routing, so nothing runs
+    /// if those are the only ones that changed. With affected only, the cargo commands only build the packages that the
+    /// changes affect.
+    pub fn select(&self, action: &Action, crate_dir: &Path) -> Vec<Command> {
+        if let Action::Scheduled(names) = action {
+            return self.schedule.iter().filter(|cmd| names.contains(&cmd.name)).cloned().collect();
+        }
+        if self.only_no_op(action) {
+            return Vec::new();
+        }
+        let mut routed = match action.paths() {
+            Some(paths) => {
+                let paths: Vec<PathBuf> = paths.iter().filter(|path| !self.is_no_op(path)).cloned().collect();
+                routes::select(&self.routes, &self.commands, crate_dir, &paths)
+            },
+            None => self.commands.clone(),
+        };
+        if let Some(affected) = action.affected().filter(|_| self.affected_only) {
+            for cmd in routed.iter_mut() {
+                cmd.select_packages(affected);
+            }
+        }
+        let only_lockfile = matches!(action, Action::LockfileChanged(_));
+        match (action.changes_dependencies(), only_lockfile && !self.lockfile_commands.is_empty()) {
+            (_, true) => self.lockfile_commands.clone(),
+            (true, false) => [&routed[..], &self.lockfile_commands[..]].concat(),
+            (false, false) => routed,
+        }
+    }
+
+    /// Check if the action is for changed files and all of them match the no-op globs
+    pub fn only_no_op(&self, action: &Action) -> bool {
+        match action.paths() {
+            Some(paths) => !paths.is_empty() && paths.iter().all(|path| self.is_no_op(path)),
+            None => false,
+        }
+    }
+
+    fn is_no_op(&self, path: &Path) -> bool {
+        !self.no_op.is_empty() && self.no_op.matched(path, false).is_whitelist()
+    }
+
+    /// The tests to run when only test files or doc comments changed, if the tests are targeted
+    ///
+    /// Only the doc tests of the changed packages run when nothing but doc comments changed. Integration tests in
+    /// `tests/foo.rs` or `tests/foo/main.rs` are picked by the name of their target, and test modules in the sources,
+    /// like `src/foo/tests.rs`, by a filter on the names of their tests. Changes to anything else, or to both kinds of
+    /// tests at once, run all the tests.
+    pub fn tests(&self, action: &Action, crate_dir: &Path) -> Option<Tests> {
+        let paths: Vec<&PathBuf> = action.paths()?.iter().filter(|path| !self.is_no_op(path)).collect();
+        if paths.is_empty() {
+            return None;
+        }
+        let mut tests = Tests::default();
+        if self.doc_tests && action.docs_only() {
+            tests.docs = Some(action.packages().to_vec());
+            return Some(tests);
+        }
+        if !self.targeted_tests {
+            return None;
+        }
+        for path in paths {
+            match test_of(crate_dir, path)? {
+                Test::Target(name) => tests.targets.push(name),
+                Test::Filter(filter) => tests.filters.push(filter),
+            }
+        }
+        if !tests.targets.is_empty() && !tests.filters.is_empty() {
+            return None;
+        }
+        tests.targets.sort();
+        tests.targets.dedup();
+        tests.filters.sort();
+        tests.filters.dedup();
+        Some(tests)
+    }
+
+    /// Copy the crate directory to the remote machine if the commands run there and it isn't shared, returns false if
+    /// it failed and the commands can't run
+    pub fn sync(&self, crate_dir: &Path) -> bool {
+        match self.ssh.as_ref().filter(|ssh| ssh.sync).map(|ssh| ssh.sync(crate_dir)) {
+            Some(Err(e)) => {
+                log::error!("{}", e);
+                false
+            },
+            _ => true,
+        }
+    }
+
+    /// The hooks to run after a run that wasn't stopped, where the failure hooks get the names of the commands that
+    /// failed in AUTO_CHECK_FAILED_COMMANDS, one per line
+    pub fn hooks(&self, run: &Run) -> Vec<Command> {
+        match run {
+            Run::Passed => self.on_success.clone(),
+            Run::Failed(names) => {
+                let mut hooks = self.on_failure.clone();
+                for hook in hooks.iter_mut() {
+                    hook.env.push(("AUTO_CHECK_FAILED_COMMANDS".into(), names.join("\n")));
+                }
+                hooks
+            },
+            Run::Stopped => Vec::new(),
+        }
+    }
+}
+
+/// The tests to run for changes to test files only
+#[derive(Debug, Default)]
+pub struct Tests {
+    /// The names of the integration tests, like `--test foo`
+    pub targets: Vec<String>,
+    /// Filters on the names of the tests, like `foo::tests::`
+    pub filters: Vec<String>,
+    /// Only the doc tests of these packages, with `--doc`
+    pub docs: Option<Vec<String>>,
+}
+
+impl Tests {
+    /// Limit the commands that run the tests to these tests
+    pub fn apply(&self, commands: &mut [Command]) {
+        for cmd in commands.iter_mut().filter(|cmd| cmd.runs_tests()) {
+            match &self.docs {
+                Some(packages) => cmd.select_doc_tests(packages),
+                None => cmd.select_tests(&self.targets, &self.filters),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Tests {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(packages) = &self.docs {
+            let packages = packages.iter().map(|name| format!(" -p {}", name)).collect::<String>();
+            return write!(f, "--doc{}", packages);
+        }
+        let targets = self.targets.iter().map(|name| format!("--test {}", name));
+        let tests: Vec<String> = targets.chain(self.filters.iter().cloned()).collect();
+        write!(f, "{}", tests.join(" "))
+    }
+}
+
+enum Test {
+    Target(String),
+    Filter(String),
+}
+
+/// Find the tests in a changed file, if it is an integration test or a test module in the sources of a package
+fn test_of(crate_dir: &Path, path: &Path) -> Option<Test> {
+    let path = crate_dir.join(path);
+    let package_dir = cargo::find_manifest_dir(path.parent()?)?;
+    let relative = path.strip_prefix(&package_dir).ok()?;
+    let parts: Vec<&str> = relative.iter().map(|part| part.to_str()).collect::<Option<_>>()?;
+    match parts[..] {
+        ["tests", file] => file.strip_suffix(".rs").map(|name| Test::Target(name.into())),
+        ["tests", name, "main.rs"] => Some(Test::Target(name.into())),
+        ["src", first, ..] if first != "bin" => {
+            let modules: Vec<&str> = parts[1..]
+                .iter()
+                .map(|part| part.trim_end_matches(".rs"))
+                .filter(|module| *module != "mod")
+                .collect();
+            if path.extension().is_none_or(|ext| ext != "rs") || !modules.contains(&"tests") {
+                return None;
+            }
+            Some(Test::Filter(format!("{}::", modules.join("::"))))
+        },
+        _ => None,
+    }
+}
@@ -0,0 +1,509 @@
+use crate::cargo;
+use crate::changes::{Action, Changes, Trigger, RUNNING_POLL};
+use crate::cli::Args;
+use crate::config::Config;
+use crate::git::{self, Tracked};
+use crate::ignores::Ignores;
+use crate::pipeline::Pipeline;
+use crate::command::{Command, RunInfo};
+use crate::runner::{self, Run};
+use crate::settings::{Debounce, Settings};
+use crate::signals;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+/// A watched crate directory with its own settings and runner thread
+pub struct Project {
+    pub crate_dir: PathBuf,
+    delay: Duration,
+    debounce: Debounce,
+    max_wait: Option<Duration>,
+    min_interval: Option<Duration>,
+    /// The names of the scheduled commands, with how often they run and when they are due next
+    schedule: Vec<(String, Duration, Instant)>,
+    /// Where the workspace is built, to find the paths the build scripts depend on
+    target_dir: Option<PathBuf>,
+    /// The target directory the commands build in instead of the one of the workspace, if they have their own
+    isolated_target_dir: Option<PathBuf>,
+    /// Creating or touching this file starts a run right away
+    trigger_file: PathBuf,
+    /// The HEAD file in the directory git keeps its own files in, to notice when git is changing the working tree
+    git_head: Option<PathBuf>,
+    /// The branch that was last checked out, a detached HEAD like in the middle of a rebase doesn't change it
+    branch: Option<String>,
+    changes: Changes,
+    action_tx: Sender<Action>,
+}
+
+impl Project {
+    /// Start the runner thread for the crate directory
+    pub fn start(crate_dir: PathBuf, settings: Settings) -> Project {
+        let mut pipeline = Pipeline::new(&settings);
+        let schedule = next_runs(&settings.schedule, &[]);
+        let Settings {
+            delay,
+            debounce,
+            max_wait,
+            min_interval,
+            run_first,
+            ignores,
+            watch,
+            ext,
+            includes,
+            tracked_only,
+            max_file_size,
+            trigger_file,
+            restart_on_change,
+            isolated_target_dir,
+            ..
+        } = settings;
+
+        let (action_tx, action_rx) = std::sync::mpsc::channel::<Action>();
+
+        let mut changes = Changes::new(&crate_dir, ignores);
+        changes.watch = watch;
+        changes.ext = ext;
+        changes.includes = includes;
+        changes.tracked = load_tracked(&crate_dir, tracked_only);
+        changes.max_file_size = max_file_size.map(|size| size.0);
+        let workspace = cargo::workspace(&crate_dir);
+        changes.packages = workspace.packages;
+        changes.scan();
+        if restart_on_change {
+            changes.cancel = Some(Default::default());
+        }
+        let running = changes.running.clone();
+        let pending = changes.pending.clone();
+        let cancel = changes.cancel.clone();
+
+        let runner_crate_dir = crate_dir.clone();
+        let workspace_target_dir = workspace.target_dir.clone();
+        std::thread::spawn(move || {
+            let crate_dir = runner_crate_dir;
+            // The names of the commands that failed in the last run that finished
+            let mut failed = Vec::new();
+            // The number of runs in a row that only ran the tests for the changed test files or doc comments
+            let mut targeted_runs = 0;
+            for action in action_rx.iter() {
+                let scheduled = matches!(action, Action::Scheduled(_));
+                // The initial, manual and branch switch runs are asked to run everything
+                let custom = matches!(action, Action::Custom(..));
+                // The earlier results no longer apply on another branch, even if its run is stopped
+                if matches!(action, Action::Custom(Trigger::Change, _)) {
+                    failed.clear();
+                }
+                let mut commands = pipeline.select(&action, &crate_dir);
+                let only_no_op = pipeline.only_no_op(&action);
+                let tests = pipeline.tests(&action, &crate_dir);
+                let changed_files = action.paths().is_some();
+                let info = RunInfo {
+                    trigger: action.trigger(),
+                    changed_files: action
+                        .paths()
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|path| crate_dir.join(path).to_string_lossy().into())
+                        .collect(),
+                    changed_crates: action.packages().to_vec(),
+                    crate_dir: crate_dir.to_string_lossy().into(),
+                };
+                info.apply(&mut commands);
+                let run_commands = match action {
+                    Action::Nothing => {
+                        log::trace!("No changes detected");
+                        false
+                    },
+                    Action::Reload(new_pipeline) => {
+                        // Reloads are sent through the same channel as the runs, so this never happens mid-run
+                        let names: Vec<_> = new_pipeline.commands.iter().map(|cmd| &cmd.name).collect();
+                        log::debug!("Using commands {:?}", names);
+                        pipeline = *new_pipeline;
+                        false
+                    },
+                    Action::Custom(_, reason) => {
+                        log::info!("{}", reason);
+                        true
+                    },
+                    Action::FilesChanged(changes) if changes.packages.is_empty() => {
+                        log::info!("Detected change: {:?}", changes.paths);
+                        true
+                    },
+                    Action::FilesChanged(changes) => {
+                        let packages: Vec<String> = changes.packages.iter().map(|name| format!("`{}`", name)).collect();
+                        let noun = if packages.len() == 1 { "crate" } else { "crates" };
+                        log::info!("Detected change in {} {}", noun, packages.join(", "));
+                        log::debug!("Changed files: {:?}", changes.paths);
+                        true
+                    },
+                    Action::GitOperation(changes) => {
+                        let noun = if changes.paths.len() == 1 { "file" } else { "files" };
+                        log::info!("Detected git operation changing {} {}", changes.paths.len(), noun);
+                        if !changes.packages.is_empty() {
+                            log::debug!("Changed crates: {:?}", changes.packages);
+                        }
+                        log::debug!("Changed files: {:?}", changes.paths);
+                        true
+                    },
+                    Action::LockfileChanged(current_paths) => {
+                        log::info!("Detected change to the lock file: {:?}", current_paths);
+                        true
+                    },
+                    Action::Scheduled(names) => {
+                        log::info!("Running the scheduled commands {}", names.join(", "));
+                        true
+                    },
+                };
+
+                let mut targeted = false;
+                match tests {
+                    Some(_) if pipeline.full_test_every > 0 && targeted_runs >= pipeline.full_test_every => {
+                        log::info!("Running all the tests after {} targeted runs", targeted_runs);
+                        targeted_runs = 0;
+                    },
+                    Some(tests) => {
+                        log::info!("Only running the tests for the changes: {}", tests);
+                        tests.apply(&mut commands);
+                        targeted = true;
+                    },
+                    None if changed_files => targeted_runs = 0,
+                    None => {},
+                }
+
+                if run_commands {
+                    if only_no_op {
+                        log::info!("Only files that don't affect the build changed, skipping the run");
+                    } else if commands.is_empty() {
+                        log::info!("No commands to run for the changed paths");
+                    } else {
+                        log::debug!("Running in {}", crate_dir.to_string_lossy());
+                        if !pipeline.sync(&crate_dir) {
+                            running.store(false, Ordering::Relaxed);
+                            continue;
+                        }
+                        // Cargo runs on the remote machine, where the local locks don't matter
+                        let target_dir = pipeline.isolated_target_dir.as_ref().or(workspace_target_dir.as_ref());
+                        if let Some(target_dir) = target_dir.filter(|_| pipeline.ssh.is_none()) {
+                            if !runner::check_locks(target_dir, pipeline.wait_for_lock, cancel.as_deref()) {
+                                running.store(false, Ordering::Relaxed);
+                                continue;
+                            }
+                        }
+                        let all_commands = match pipeline.failed_first && !scheduled && !custom {
+                            true => only_failed(&mut commands, &failed),
+                            false => None,
+                        };
+                        let keep_going = pipeline.keep_going;
+                        let run_tiers = |commands: &[Command]| {
+                            runner::run_tiers(commands, &crate_dir, cancel.as_deref(), keep_going, Some(&pending))
+                        };
+                        if scheduled {
+                            // The scheduled commands don't depend on each other or on the code, so they all run
+                            // without being stopped by changes, and the results don't count for the other commands
+                            runner::run_commands(&commands, &crate_dir, None, true);
+                            println!();
+                            running.store(false, Ordering::Relaxed);
+                            continue;
+                        }
+                        // Only a run that runs the tests counts towards running all of them again
+                        if targeted && commands.iter().any(|cmd| cmd.runs_tests()) {
+                            targeted_runs += 1;
+                        }
+                        let mut run = run_tiers(&commands);
+                        if let (Run::Passed, Some(all_commands)) = (&run, all_commands) {
+                            println!();
+                            log::info!("The commands that failed pass now, running all of them again");
+                            run = run_tiers(&all_commands);
+                        }
+                        let mut hooks = pipeline.hooks(&run);
+                        info.apply(&mut hooks);
+                        runner::run_hooks(&hooks, &crate_dir);
+                        signals::record(&crate_dir, &run);
+                        match run {
+                            Run::Passed => failed.clear(),
+                            Run::Failed(names) => failed = names,
+                            // A stopped run doesn't say anything about the commands that didn't finish
+                            Run::Stopped => {},
+                        }
+                        println!();
+                    }
+                    running.store(false, Ordering::Relaxed);
+                }
+            }
+        });
+
+        if run_first {
+            changes.add_custom(Trigger::Initial, "Initial check");
+        }
+        let git_head = git::git_dir(&crate_dir).map(|git_dir| git_dir.join("HEAD"));
+        let branch = git_head.as_deref().and_then(git::current_branch);
+
+        let mut project = Project {
+            crate_dir,
+            delay,
+            debounce,
+            max_wait,
+            min_interval,
+            schedule,
+            target_dir: isolated_target_dir.clone().or(workspace.target_dir),
+            isolated_target_dir,
+            trigger_file,
+            git_head,
+            branch,
+            changes,
+            action_tx,
+        };
+        project.refresh_build_inputs();
+        project
+    }
+
+    /// Record a changed path, reloading the config file if it was the one that changed
+    ///
+    /// Returns true if the settings or the ignore rules were reloaded, since there may be new paths to watch.
+    pub fn add(&mut self, path: &Path, args: &Args) -> bool {
+        if self.trigger(path) {
+            return false;
+        }
+        if self.is_git_file(path) {
+            if let Some(tracked) = self.changes.tracked.as_mut().filter(|tracked| tracked.index() == path) {
+                log::debug!("Reading the tracked files again after a change to the git index");
+                tracked.reload();
+            }
+            self.changes.add_git_activity();
+            if self.git_head.as_deref() == Some(path) {
+                self.check_branch(path);
+            }
+            return false;
+        }
+        let mut reloaded = Config::is_config_file(&self.crate_dir, path) && self.reload(args);
+        if Ignores::is_ignore_file(path) {
+            log::info!("Reloading the ignore rules after a change to {}", path.to_string_lossy());
+            self.changes.ignores.reload();
+            reloaded = true;
+        }
+        if path.file_name().is_some_and(|name| name == "Cargo.toml") {
+            // Packages may have been added to or removed from the workspace
+            let workspace = cargo::workspace(&self.crate_dir);
+            self.changes.packages = workspace.packages;
+            self.target_dir = self.isolated_target_dir.clone().or(workspace.target_dir);
+        }
+        self.changes.add(&path);
+        reloaded
+    }
+
+    /// Start a run if the path is the trigger file and it is there, returns true if it is the trigger file
+    pub fn trigger(&mut self, path: &Path) -> bool {
+        if path != self.trigger_file {
+            return false;
+        }
+        if path.exists() {
+            self.changes.add_custom(Trigger::Manual, format!("Triggered by {}", path.to_string_lossy()));
+        }
+        true
+    }
+
+    /// Start a run right away, without waiting for any changes
+    pub fn run_now(&mut self, reason: &str) {
+        self.changes.add_custom(Trigger::Manual, reason);
+    }
+
+    /// Look for changes that were missed because events were lost
+    pub fn resync(&mut self) {
+        match self.changes.resync() {
+            0 => log::debug!("Found no missed changes in {}", self.crate_dir.to_string_lossy()),
+            count => log::info!("Found {} missed changes in {}", count, self.crate_dir.to_string_lossy()),
+        }
+    }
+
+    /// Record that a path was renamed, so changes already recorded below it use the new path
+    ///
+    /// Returns true if the path was already recorded as changed, and only the new path has to be recorded.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> bool {
+        self.changes.rename(from, to)
+    }
+
+    /// The files and directories outside the crate directory that are also watched, including the files git keeps
+    pub fn watch_paths(&self) -> impl Iterator<Item = &Path> {
+        let build_inputs = self.changes.build_inputs.iter().filter(move |path| !path.starts_with(&self.crate_dir));
+        let trigger_file = Some(&self.trigger_file).filter(|path| !path.starts_with(&self.crate_dir));
+        self.changes
+            .watch
+            .iter()
+            .chain(build_inputs)
+            .chain(trigger_file)
+            .map(PathBuf::as_path)
+            .chain(self.git_head.as_deref())
+    }
+
+    /// Check if a path is inside one of the extra watched paths, or is one of the files git keeps
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.changes.is_watched(path)
+            || self.changes.is_build_input(path)
+            || path == self.trigger_file
+            || self.is_git_file(path)
+    }
+
+    /// Run everything when another branch is checked out, since the earlier results no longer apply
+    fn check_branch(&mut self, head: &Path) {
+        let branch = match git::current_branch(head) {
+            Some(branch) if self.branch.as_ref() != Some(&branch) => branch,
+            _ => return,
+        };
+        self.changes.add_custom(Trigger::Change, format!("Switched to branch {}", branch));
+        self.branch = Some(branch);
+    }
+
+    /// Check if a path is one of the files directly in the directory git keeps its own files in
+    fn is_git_file(&self, path: &Path) -> bool {
+        self.git_head.as_deref().is_some_and(|head| head.parent() == path.parent())
+    }
+
+    /// Check if a directory inside the crate directory is ignored, so there is no need to watch it
+    pub fn is_ignored_dir(&self, path: &Path) -> bool {
+        self.changes.is_ignored(path, true)
+    }
+
+    /// When the current changes or the next scheduled commands should be run, if there are any
+    pub fn deadline(&self) -> Option<Instant> {
+        let changes = self.changes.deadline(self.delay, self.debounce, self.max_wait, self.min_interval);
+        let scheduled = self.schedule.iter().map(|(_, _, next)| *next).min().map(|next| {
+            // Nothing new is started until the current run is done
+            match self.changes.running.load(Ordering::Relaxed) {
+                true => next.max(Instant::now() + RUNNING_POLL),
+                false => next,
+            }
+        });
+        changes.into_iter().chain(scheduled).min()
+    }
+
+    /// Tell the runner thread what to do once the deadline is reached
+    ///
+    /// The changes go first, and the scheduled commands that are due run once nothing else is running. Returns true if
+    /// the build scripts depend on new paths since the last run, since they have to be watched.
+    pub fn publish(&mut self) -> bool {
+        let now = Instant::now();
+        let changes = self.changes.deadline(self.delay, self.debounce, self.max_wait, self.min_interval);
+        if changes.is_some_and(|deadline| deadline <= now) {
+            self.action_tx
+                .send(self.changes.take_current_action())
+                .expect("Failed to publish action");
+            return self.refresh_build_inputs();
+        }
+        if self.changes.running.load(Ordering::Relaxed) {
+            return false;
+        }
+        let mut due = Vec::new();
+        for (name, every, next) in self.schedule.iter_mut().filter(|(_, _, next)| *next <= now) {
+            due.push(name.clone());
+            *next = now + *every;
+        }
+        if !due.is_empty() {
+            self.changes.running.store(true, Ordering::Relaxed);
+            self.action_tx.send(Action::Scheduled(due)).expect("Failed to publish action");
+        }
+        false
+    }
+
+    /// Read the paths the build scripts depend on again, returns true if there are new ones
+    fn refresh_build_inputs(&mut self) -> bool {
+        let build_inputs = match &self.target_dir {
+            Some(target_dir) => cargo::build_inputs(target_dir, &self.changes.packages),
+            None => return false,
+        };
+        let added = build_inputs.difference(&self.changes.build_inputs).count();
+        if added > 0 {
+            log::debug!("The build scripts depend on {:?}", build_inputs);
+        }
+        self.changes.build_inputs = build_inputs;
+        added > 0
+    }
+
+    fn reload(&mut self, args: &Args) -> bool {
+        match Settings::load(args, &self.crate_dir) {
+            Ok(settings) => {
+                log::info!("Reloaded the configuration for {}", self.crate_dir.to_string_lossy());
+                let pipeline = Pipeline::new(&settings);
+                self.schedule = next_runs(&settings.schedule, &self.schedule);
+                self.delay = settings.delay;
+                self.debounce = settings.debounce;
+                self.max_wait = settings.max_wait;
+                self.min_interval = settings.min_interval;
+                self.changes.ignores = settings.ignores;
+                self.changes.watch = settings.watch;
+                self.changes.ext = settings.ext;
+                self.changes.includes = settings.includes;
+                self.changes.max_file_size = settings.max_file_size.map(|size| size.0);
+                self.trigger_file = settings.trigger_file;
+                if settings.isolated_target_dir != self.isolated_target_dir {
+                    let crate_dir = &self.crate_dir;
+                    self.target_dir =
+                        settings.isolated_target_dir.clone().or_else(|| cargo::workspace(crate_dir).target_dir);
+                    self.isolated_target_dir = settings.isolated_target_dir;
+                }
+                if settings.tracked_only != self.changes.tracked.is_some() {
+                    self.changes.tracked = load_tracked(&self.crate_dir, settings.tracked_only);
+                }
+                self.action_tx
+                    .send(Action::Reload(Box::new(pipeline)))
+                    .expect("Failed to publish action");
+                true
+            },
+            Err(e) => {
+                log::error!("Failed to reload the configuration, keeping the old one: {}", e);
+                false
+            },
+        }
+    }
+}
+
+/// Read the files tracked by git in the crate directory if only changes to them should trigger a run
+fn load_tracked(crate_dir: &Path, tracked_only: bool) -> Option<Tracked> {
+    if !tracked_only {
+        return None;
+    }
+    let tracked = Tracked::new(crate_dir);
+    if tracked.is_none() {
+        log::warn!("Can't find the files tracked by git in {}, using all of them", crate_dir.to_string_lossy());
+    }
+    tracked
+}
+
+/// Only keep the commands that failed in the last run, returns all the commands if any of them were left out
+///
+/// Nothing is left out if none of the commands failed, like when the changes are routed to other commands.
+fn only_failed(commands: &mut Vec<Command>, failed: &[String]) -> Option<Vec<Command>> {
+    let retry: Vec<Command> = commands.iter().filter(|cmd| failed.contains(&cmd.name)).cloned().collect();
+    if retry.is_empty() || retry.len() == commands.len() {
+        return None;
+    }
+    let names: Vec<&str> = retry.iter().map(|cmd| cmd.name.as_str()).collect();
+    log::info!("Only running the commands that failed: {}", names.join(", "));
+    Some(std::mem::replace(commands, retry))
+}
+
+/// Find the index of the project that a path belongs to, picking the innermost one if the crate directories are nested
+pub fn find(projects: &[Project], path: &Path) -> Option<usize> {
+    projects
+        .iter()
+        .enumerate()
+        .filter(|(_, project)| path.starts_with(&project.crate_dir))
+        .max_by_key(|(_, project)| project.crate_dir.components().count())
+        .map(|(index, _)| index)
+}
+
+/// When the scheduled commands are due next, where the ones that are new or run at another interval are due right away
+fn next_runs(schedule: &[Command], old: &[(String, Duration, Instant)]) -> Vec<(String, Duration, Instant)> {
+    let now = Instant::now();
+    schedule
+        .iter()
+        .filter_map(|cmd| Some((cmd.name.clone(), cmd.every?)))
+        .map(|(name, every)| {
+            let next = old
+                .iter()
+                .find(|(old_name, old_every, _)| *old_name == name && *old_every == every)
+                .map_or(now, |(_, _, next)| *next);
+            (name, every, next)
+        })
+        .collect()
+}
@@ -0,0 +1,149 @@
+use crate::command::Command;
+use std::path::Path;
+use std::process::Stdio;
+
+/// Running the commands in a container, with the crate directory mounted at the same path as here
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    /// The program that runs the containers, like docker or podman
+    pub engine: String,
+    pub image: String,
+    /// Other arguments for the engine, like volumes for the caches of cargo
+    pub args: Vec<String>,
+}
+
+impl Container {
+    /// The command that runs the arguments in a new container, in the same directory as they would run here
+    ///
+    /// The container is removed when the command exits, and the signals that stop the command are passed on to it.
+    /// The files it writes belong to the user here, and the memory and CPU limits are the ones of the container.
+    pub fn command(&self, cmd: &Command, args: &[String], env: &[(String, String)], crate_dir: &Path) -> Vec<String> {
+        let dir = crate_dir.to_string_lossy();
+        let cwd = cmd.cwd.as_ref().map_or_else(|| crate_dir.into(), |cwd| crate_dir.join(cwd));
+        let mut run: Vec<String> = vec![self.engine.clone(), "run".into(), "--rm".into(), "--init".into()];
+        run.extend(["--volume".into(), format!("{}:{}", dir, dir)]);
+        run.extend(["--workdir".into(), cwd.to_string_lossy().into()]);
+        if cmd.pty {
+            run.push("--tty".into());
+        }
+        #[cfg(unix)]
+        if Path::new(&self.engine).file_name().is_some_and(|name| name == "podman") {
+            run.push("--userns=keep-id".into());
+        } else {
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            run.extend(["--user".into(), format!("{}:{}", uid, gid)]);
+        }
+        if let Some(memory_limit) = cmd.memory_limit {
+            run.extend(["--memory".into(), memory_limit.to_string(), "--memory-swap".into(), memory_limit.to_string()]);
+        }
+        if let Some(cpu_limit) = cmd.cpu_limit {
+            run.extend(["--cpus".into(), cpu_limit.to_string()]);
+        }
+        for (key, value) in env {
+            run.extend(["--env".into(), format!("{}={}", key, value)]);
+        }
+        run.extend(self.args.iter().cloned());
+        run.push(self.image.clone());
+        run.extend(args.iter().cloned());
+        run
+    }
+}
+
+impl std::fmt::Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} with {}", self.image, self.engine)
+    }
+}
+
+/// Find the program that runs the containers, podman if it is installed and docker otherwise
+pub fn container_engine() -> String {
+    let podman = std::process::Command::new("podman")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if podman {
+        "podman".into()
+    } else {
+        "docker".into()
+    }
+}
+
+/// Running the commands on another machine over SSH, in a checkout of the crate there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ssh {
+    /// Where to connect to, like `user@host` or a host from the SSH config
+    pub host: String,
+    /// The directory of the crate on the remote machine, relative to the home directory there unless it is absolute
+    pub dir: String,
+    /// Copy the crate directory to the remote machine before every run, instead of relying on a shared checkout
+    pub sync: bool,
+}
+
+impl Ssh {
+    /// The ssh command that runs the arguments in the directory of the crate on the remote machine
+    ///
+    /// The environment variables of the command are set on the remote side, since ssh doesn't pass them on. With `tty`
+    /// the command gets a terminal there, which also stops it when the connection is closed.
+    pub fn command(&self, args: &[String], env: &[(String, String)], cwd: Option<&Path>, tty: bool) -> Vec<String> {
+        let dir = match cwd {
+            Some(cwd) => format!("{}/{}", self.dir.trim_end_matches('/'), cwd.to_string_lossy()),
+            None => self.dir.clone(),
+        };
+        let mut line = format!("cd {} &&", shell_words::quote(&dir));
+        if !env.is_empty() {
+            line.push_str(" env");
+            for (key, value) in env {
+                line.push(' ');
+                line.push_str(&shell_words::quote(&format!("{}={}", key, value)));
+            }
+        }
+        line.push(' ');
+        line.push_str(&shell_words::join(args));
+
+        let mut ssh: Vec<String> = vec!["ssh".into(), "-o".into(), "BatchMode=yes".into()];
+        if tty {
+            ssh.push("-tt".into());
+        }
+        ssh.extend([self.host.clone(), "--".into(), line]);
+        ssh
+    }
+
+    /// Copy the crate directory to the remote machine with rsync
+    ///
+    /// The target directory and the files git ignores are left out, and are kept on the remote side, while the files
+    /// that were removed here are removed there too.
+    pub fn sync(&self, crate_dir: &Path) -> Result<(), String> {
+        let source = format!("{}/", crate_dir.to_string_lossy());
+        let destination = format!("{}:{}/", self.host, self.dir.trim_end_matches('/'));
+        log::debug!("Copying {} to {}", source, destination);
+        let output = std::process::Command::new("rsync")
+            .args(["--archive", "--compress", "--delete", "--exclude", "/target/", "--filter", ":- .gitignore"])
+            .args(["--rsh", "ssh -o BatchMode=yes"])
+            .arg(&source)
+            .arg(&destination)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| format!("Failed to run rsync: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to copy the crate directory to {}: {}",
+                destination,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Ssh {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.dir)?;
+        if self.sync {
+            write!(f, ", synced")?;
+        }
+        Ok(())
+    }
+}
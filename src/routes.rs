@@ -0,0 +1,42 @@
+use crate::command::Command;
+use std::path::{Path, PathBuf};
+
+/// Commands that run instead of the normal ones for changes below a path
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// The absolute path the changes have to be below
+    pub path: PathBuf,
+    pub commands: Vec<Command>,
+}
+
+/// Pick the commands to run for the changed paths, which are relative to the crate directory or absolute
+///
+/// Each path uses the route with the longest path it is below, or the normal commands if there is none. The commands
+/// for all the paths are merged, with the normal commands first and then the ones from the routes in the order they
+/// are configured, leaving out any command that is already there.
+pub fn select(routes: &[Route], commands: &[Command], crate_dir: &Path, paths: &[PathBuf]) -> Vec<Command> {
+    let mut normal = false;
+    let mut used = vec![false; routes.len()];
+    for path in paths {
+        let path = crate_dir.join(path);
+        let route = routes
+            .iter()
+            .enumerate()
+            .filter(|(_, route)| path.starts_with(&route.path))
+            .max_by_key(|(_, route)| route.path.components().count());
+        match route {
+            Some((index, _)) => used[index] = true,
+            None => normal = true,
+        }
+    }
+
+    let mut selected: Vec<Command> = if normal { commands.to_vec() } else { Vec::new() };
+    for route in routes.iter().zip(used).filter(|(_, used)| *used).map(|(route, _)| route) {
+        for cmd in route.commands.iter() {
+            if !selected.iter().any(|selected| selected.name == cmd.name && selected.args == cmd.args) {
+                selected.push(cmd.clone());
+            }
+        }
+    }
+    selected
+}
@@ -0,0 +1,1096 @@
+use crate::cargo;
+use crate::command::Command;
+use crate::config::ENV_PREFIX;
+use crate::deny;
+use crate::hashes;
+use crate::nextest;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often to check if a command that can be stopped has finished
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// How often to check if another cargo process has released its lock, when waiting for it
+const LOCK_POLL: Duration = Duration::from_millis(200);
+
+/// How many of the slowest tests to log after cargo nextest ran them
+const SLOWEST_TESTS: usize = 3;
+
+/// How long a stopped command gets to exit after being asked to before it is killed
+#[cfg(unix)]
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+/// The process ids of the commands that are running, which are also the ids of their process groups
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// The job objects the commands that are running are in, with the process ids of the commands
+#[cfg(windows)]
+static JOBS: Mutex<Vec<(u32, Job)>> = Mutex::new(Vec::new());
+
+/// Set when quitting, so the commands that are stopped aren't reported as failures or followed by anything else
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// The lines of output from a quiet command, with whether they were written to stderr
+type Captured = Arc<Mutex<Vec<(bool, String)>>>;
+
+/// What cargo said in its JSON messages while a command ran
+#[derive(Default)]
+struct Messages {
+    /// Whether the build succeeded, once cargo says so
+    built: Option<bool>,
+    /// The warnings and errors from the compiler, without the ones that were repeated
+    diagnostics: Vec<cargo::Diagnostic>,
+    /// The tests that finished, when cargo nextest runs them
+    tests: Vec<nextest::TestResult>,
+    /// What each of the checks found, when cargo deny runs them
+    checks: Vec<deny::Check>,
+}
+
+/// Where the lines of output from a command go
+#[derive(Clone)]
+struct Sink {
+    name: String,
+    prefixed: bool,
+    /// Keep the lines instead of printing them, for a quiet command
+    captured: Option<Captured>,
+    /// Read the JSON messages from cargo in the output, printing the diagnostics and keeping what they said
+    messages: Option<Arc<Mutex<Messages>>>,
+}
+
+/// How a command that was waited for finished
+enum Outcome {
+    Exited(ExitStatus),
+    /// The command was stopped since the run was cancelled
+    Cancelled,
+    /// The command was killed since it ran for longer than its timeout
+    TimedOut(Duration),
+}
+
+/// How a run of the commands went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Run {
+    Passed,
+    /// The names of the commands that failed
+    Failed(Vec<String>),
+    /// The run was stopped before it was done
+    Stopped,
+}
+
+impl Run {
+    pub fn passed(&self) -> bool {
+        *self == Run::Passed
+    }
+}
+
+/// What happened to a command in a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Passed,
+    Failed,
+    /// The code compiled but some of the tests failed, which doesn't stop the commands after it
+    TestsFailed,
+    /// The command didn't run since a command before it or one it depends on failed
+    Skipped,
+}
+
+/// Run the commands in the crate directory, stopping at the first failure unless `keep_going` is set
+///
+/// A `cargo test` command where only the tests failed doesn't stop the commands after it, since they can still tell
+/// something about the code, but one where the code didn't compile does.
+///
+/// The commands run in their own process group, or job object on Windows, so the processes they start, like test
+/// binaries, are stopped together with them, either when they time out or if `cancel` is given and it is set. A command
+/// that fails is run again up to its number of retries.
+///
+/// If any of the commands depend on others they run as soon as the commands they depend on have passed, otherwise they
+/// run in order and only the commands next to each other that are marked as parallel run at the same time. Commands
+/// that run at the same time have their output prefixed by their names.
+pub fn run_commands(commands: &[Command], crate_dir: &Path, cancel: Option<&AtomicBool>, keep_going: bool) -> Run {
+    let graph = commands.iter().any(|cmd| !cmd.depends_on.is_empty());
+    let results = if graph {
+        run_graph(commands, crate_dir, cancel, keep_going)
+    } else {
+        run_in_order(commands, crate_dir, cancel, keep_going)
+    };
+    match results {
+        Some(results) => report(commands, &results, keep_going || graph),
+        // A stopped run doesn't go on with the other commands
+        None => Run::Stopped,
+    }
+}
+
+/// Warn if another cargo process, like rust-analyzer, holds a lock the commands need, and wait for it if `wait` is set
+///
+/// Cargo only says it is blocking when it is in the middle of a command, which makes the commands look stuck. Returns
+/// false if the run was cancelled while waiting.
+pub fn check_locks(target_dir: &Path, wait: bool, cancel: Option<&AtomicBool>) -> bool {
+    let held = cargo::held_locks(target_dir);
+    if held.is_empty() {
+        return true;
+    }
+    let paths: Vec<_> = held.iter().map(|path| path.to_string_lossy()).collect();
+    if !wait {
+        log::warn!("Another cargo process holds the lock on {}, the commands wait for it", paths.join(", "));
+        return true;
+    }
+    log::warn!("Waiting for another cargo process to release the lock on {}", paths.join(", "));
+    let start = Instant::now();
+    while !cargo::held_locks(target_dir).is_empty() {
+        if STOPPING.load(Ordering::Relaxed) || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            log::info!("Stopped waiting for the lock");
+            return false;
+        }
+        std::thread::sleep(LOCK_POLL);
+    }
+    log::info!("The lock was released after {:.1?}", start.elapsed());
+    true
+}
+
+/// Run the hooks after a run one at a time, where a hook that fails is only logged since it doesn't change the result
+pub fn run_hooks(hooks: &[Command], crate_dir: &Path) {
+    for hook in hooks {
+        if run_command(hook, crate_dir, None, false) != Some(Status::Passed) {
+            log::warn!("The {} hook failed", hook.name);
+        }
+    }
+}
+
+/// Run the fast commands first, and then the slow ones if the fast ones passed and there are no changes `pending`
+///
+/// Leaving out the slow commands means the next run starts sooner, and it doesn't say anything about them, so the run
+/// counts as stopped then. Without any slow commands this is the same as `run_commands`.
+pub fn run_tiers(
+    commands: &[Command],
+    crate_dir: &Path,
+    cancel: Option<&AtomicBool>,
+    keep_going: bool,
+    pending: Option<&AtomicBool>,
+) -> Run {
+    let (slow, fast): (Vec<Command>, Vec<Command>) = commands.iter().cloned().partition(|cmd| cmd.slow);
+    if slow.is_empty() || fast.is_empty() {
+        return run_commands(commands, crate_dir, cancel, keep_going);
+    }
+    let run = run_commands(&fast, crate_dir, cancel, keep_going);
+    if !run.passed() {
+        return run;
+    }
+    let names: Vec<&str> = slow.iter().map(|cmd| cmd.name.as_str()).collect();
+    if pending.is_some_and(|pending| pending.load(Ordering::Relaxed)) {
+        log::info!("Leaving out the slow commands {} until the next changes are checked", names.join(", "));
+        return Run::Stopped;
+    }
+    println!();
+    log::info!("Running the slow commands {}", names.join(", "));
+    run_commands(&slow, crate_dir, cancel, keep_going)
+}
+
+/// Run the commands in order, returns the status and the number of retries for each of them, or None if stopped
+fn run_in_order(
+    commands: &[Command],
+    crate_dir: &Path,
+    cancel: Option<&AtomicBool>,
+    keep_going: bool,
+) -> Option<Vec<(Status, u32)>> {
+    let mut results = vec![(Status::Skipped, 0); commands.len()];
+    let mut start = 0;
+    for batch in batches(commands) {
+        let finished: Vec<(Option<Status>, u32)> = if batch.len() == 1 {
+            vec![run_with_retries(&batch[0], crate_dir, cancel, false)]
+        } else {
+            let names: Vec<&str> = batch.iter().map(|cmd| cmd.name.as_str()).collect();
+            log::info!("Running {} at the same time", names.join(", "));
+            std::thread::scope(|scope| {
+                let threads: Vec<_> = batch
+                    .iter()
+                    .map(|cmd| scope.spawn(move || run_with_retries(cmd, crate_dir, cancel, true)))
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().expect("Failed to run a command"))
+                    .collect()
+            })
+        };
+
+        let mut failed = false;
+        for (result, (status, retries)) in results[start..].iter_mut().zip(finished) {
+            let status = status?;
+            failed |= status == Status::Failed;
+            *result = (status, retries);
+        }
+        start += batch.len();
+        if failed && !keep_going {
+            break;
+        }
+    }
+    Some(results)
+}
+
+/// Run every command as soon as the commands it depends on have passed, with as many at the same time as possible
+///
+/// A dependency on a name that isn't in the commands, like one that was skipped, is left out. Returns the status and
+/// the number of retries for each of the commands, or None if stopped.
+fn run_graph(
+    commands: &[Command],
+    crate_dir: &Path,
+    cancel: Option<&AtomicBool>,
+    keep_going: bool,
+) -> Option<Vec<(Status, u32)>> {
+    let dependencies: Vec<Vec<usize>> = commands
+        .iter()
+        .map(|cmd| {
+            (0..commands.len())
+                .filter(|&other| cmd.depends_on.contains(&commands[other].name))
+                .collect()
+        })
+        .collect();
+
+    let mut results: Vec<Option<(Status, u32)>> = vec![None; commands.len()];
+    let mut started = vec![false; commands.len()];
+    let mut stopped = false;
+    std::thread::scope(|scope| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut running = 0;
+        loop {
+            let failed = results.iter().flatten().any(|(status, _)| *status == Status::Failed);
+            // Skipping a command can make the ones depending on it skipped as well
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for index in 0..commands.len() {
+                    if started[index] || results[index].is_some() {
+                        continue;
+                    }
+                    let statuses: Vec<_> = dependencies[index].iter().map(|&dep| results[dep].map(|r| r.0)).collect();
+                    let passed = |status: &Option<Status>| *status == Some(Status::Passed);
+                    if statuses.iter().any(|status| !passed(status) && status.is_some()) {
+                        results[index] = Some((Status::Skipped, 0));
+                        changed = true;
+                    } else if statuses.iter().all(passed)
+                        && !stopped
+                        && (keep_going || !failed)
+                    {
+                        started[index] = true;
+                        running += 1;
+                        let tx = tx.clone();
+                        let cmd = &commands[index];
+                        scope.spawn(move || {
+                            let _ = tx.send((index, run_with_retries(cmd, crate_dir, cancel, true)));
+                        });
+                    }
+                }
+            }
+            if running == 0 {
+                break;
+            }
+            let (index, (status, retries)) = rx.recv().expect("The command threads are still running");
+            running -= 1;
+            match status {
+                Some(status) => results[index] = Some((status, retries)),
+                None => {
+                    results[index] = Some((Status::Skipped, retries));
+                    stopped = true;
+                },
+            }
+        }
+    });
+    if stopped {
+        return None;
+    }
+    Some(results.into_iter().map(|result| result.unwrap_or((Status::Skipped, 0))).collect())
+}
+
+/// Log how the run went, with the status of every command if `summary` is set
+fn report(commands: &[Command], results: &[(Status, u32)], summary: bool) -> Run {
+    let names = |statuses: &[Status]| -> Vec<&str> {
+        commands
+            .iter()
+            .zip(results)
+            .filter(|(_, result)| statuses.contains(&result.0))
+            .map(|(cmd, _)| cmd.name.as_str())
+            .collect()
+    };
+    let retried: Vec<String> = commands
+        .iter()
+        .zip(results)
+        .filter(|(_, result)| result.1 > 0)
+        .map(|(cmd, (_, retries))| format!("{} ({} {})", cmd.name, retries, plural_retries(*retries)))
+        .collect();
+
+    let failed = names(&[Status::Failed, Status::TestsFailed]);
+    if failed.is_empty() {
+        if !retried.is_empty() {
+            log::warn!("All the commands succeeded after retrying {}", retried.join(", "));
+        }
+        return Run::Passed;
+    }
+    // The commands after failing tests still run, so it isn't only the last command that failed
+    if summary || results.iter().any(|result| result.0 == Status::TestsFailed) {
+        log::error!("Failed: {}", failed.join(", "));
+        let passed = names(&[Status::Passed]);
+        if !passed.is_empty() {
+            log::info!("Passed: {}", passed.join(", "));
+        }
+        let skipped = names(&[Status::Skipped]);
+        if !skipped.is_empty() {
+            log::warn!("Skipped: {}", skipped.join(", "));
+        }
+    }
+    Run::Failed(failed.into_iter().map(String::from).collect())
+}
+
+/// Split the commands into the ones that run on their own, and the ones next to each other that run at the same time
+fn batches(commands: &[Command]) -> Vec<&[Command]> {
+    let mut batches = Vec::new();
+    let mut rest = commands;
+    while let Some(first) = rest.first() {
+        let len = match first.parallel {
+            true => rest.iter().take_while(|cmd| cmd.parallel).count(),
+            false => 1,
+        };
+        let (batch, remaining) = rest.split_at(len);
+        batches.push(batch);
+        rest = remaining;
+    }
+    batches
+}
+
+/// Run a command until it succeeds or it runs out of retries
+///
+/// Returns how it went, or None if it was stopped, together with the number of retries.
+fn run_with_retries(
+    cmd: &Command,
+    crate_dir: &Path,
+    cancel: Option<&AtomicBool>,
+    prefixed: bool,
+) -> (Option<Status>, u32) {
+    let mut retries = 0;
+    loop {
+        match run_command(cmd, crate_dir, cancel, prefixed) {
+            Some(status) if status != Status::Passed && retries < cmd.retries => {
+                retries += 1;
+                log::warn!("Retrying the {} command, retry {} of {}", cmd.name, retries, cmd.retries);
+            },
+            Some(status) if status != Status::Passed && retries > 0 => {
+                log::error!("The {} command still failed after {} {}", cmd.name, retries, plural_retries(retries));
+                return (Some(status), retries);
+            },
+            status => return (status, retries),
+        }
+    }
+}
+
+fn plural_retries(retries: u32) -> &'static str {
+    if retries == 1 {
+        "retry"
+    } else {
+        "retries"
+    }
+}
+
+/// Run a single command, returns how it went, or None if it was stopped
+///
+/// A command that couldn't be started, like one that isn't installed, counts as failed.
+///
+/// If `prefixed`, or the command asks for it, the output of the command is read line by line and printed after its
+/// name, so the output of commands that run at the same time can be told apart. A quiet command only prints a line
+/// with its name, and its output only if it fails.
+///
+/// A cargo command that builds the code gets the JSON messages from cargo, which tell if the code compiled before the
+/// tests failed. The diagnostics in them are printed like cargo does without JSON, but only once, since the same code
+/// is often compiled more than once, like for the library and its tests.
+fn run_command(cmd: &Command, crate_dir: &Path, cancel: Option<&AtomicBool>, prefixed: bool) -> Option<Status> {
+    let prefixed = prefixed || cmd.prefix;
+    let messages =
+        (cmd.json_messages() || cmd.nextest_messages() || cmd.deny_messages()).then(Arc::<Mutex<Messages>>::default);
+    let by_line = prefixed || cmd.quiet || messages.is_some();
+    let sink = Sink {
+        name: cmd.name.clone(),
+        prefixed,
+        captured: cmd.quiet.then(Captured::default),
+        messages: messages.clone(),
+    };
+    if !cmd.quiet {
+        println!();
+    }
+    log::info!("Running {} command {:?}", cmd.name, cmd.args);
+    let has_env = |key: &str| cmd.env.iter().any(|(name, _)| name == key);
+    let mut args = cmd.args.clone();
+    if cmd.nextest_messages() {
+        let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+        args.splice(end..end, ["--message-format".to_string(), "libtest-json".to_string()]);
+    } else if cmd.deny_messages() {
+        // The format is an option of cargo deny itself, which comes before the check
+        let deny = args.iter().position(|arg| arg == "deny").map_or(args.len(), |index| index + 1);
+        args.splice(deny..deny, ["--format".to_string(), "json".to_string()]);
+    } else if messages.is_some() {
+        let color = cmd.env.iter().rfind(|(key, _)| key == "CARGO_TERM_COLOR").map(|(_, value)| value.as_str());
+        let ansi = match color {
+            Some(color) => color == "always",
+            None => cmd.pty || std::io::stderr().is_terminal(),
+        };
+        let format = match ansi {
+            true => "--message-format=json-diagnostic-rendered-ansi",
+            false => "--message-format=json",
+        };
+        let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+        args.insert(end, format.into());
+    }
+    // The environment is set on the other side, where cargo only uses colors when writing to a terminal like with --pty
+    let remote_env = || {
+        let mut env = cmd.env.clone();
+        if by_line && std::io::stdout().is_terminal() && !has_env("CARGO_TERM_COLOR") {
+            env.push(("CARGO_TERM_COLOR".into(), "always".into()));
+        }
+        env
+    };
+    let args = match (&cmd.ssh, &cmd.container) {
+        (Some(ssh), _) => ssh.command(&args, &remote_env(), cmd.cwd.as_deref(), cmd.pty),
+        (None, Some(container)) => container.command(cmd, &args, &remote_env(), crate_dir),
+        (None, None) => limit_resources(cmd, args),
+    };
+    let mut command = std::process::Command::new(&args[0]);
+    command.current_dir(cmd.cwd.as_ref().map_or_else(|| crate_dir.into(), |cwd| crate_dir.join(cwd)));
+    command.args(&args[1..]);
+    // The variables about the run can be long lists of files, so they are left out of the command that is logged
+    let (run_env, env): (Vec<_>, Vec<_>) = cmd.env.iter().partition(|(key, _)| key.starts_with(ENV_PREFIX));
+    command.envs(env.into_iter().map(|(key, value)| (key, value)));
+    let description = format!("{:?}", command);
+    command.envs(run_env.into_iter().map(|(key, value)| (key, value)));
+    // Reading the terminal from another process group stops the process, and the input is for auto-check-rs
+    command.stdin(Stdio::null());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    #[cfg(unix)]
+    if let Some(nice) = cmd.nice {
+        lower_priority(&mut command, nice);
+    }
+    let pty = match cmd.pty {
+        true => open_pty()
+            .map_err(|e| log::warn!("Failed to open a terminal for the {} command: {}", cmd.name, e))
+            .ok(),
+        false => None,
+    };
+    let terminal = match pty {
+        Some((terminal, out, err)) => {
+            command.stdout(out);
+            command.stderr(err);
+            // A progress bar is redrawn on the same line, which doesn't work when the output is read line by line
+            if by_line && !has_env("CARGO_TERM_PROGRESS_WHEN") {
+                command.env("CARGO_TERM_PROGRESS_WHEN", "never");
+            }
+            Some(terminal)
+        },
+        None if by_line => {
+            command.stdout(Stdio::piped());
+            command.stderr(Stdio::piped());
+            // Cargo only uses colors when writing to a terminal, and the output still ends up in one
+            if std::io::stdout().is_terminal() && !has_env("CARGO_TERM_COLOR") {
+                command.env("CARGO_TERM_COLOR", "always");
+            }
+            None
+        },
+        None => None,
+    };
+
+    let writes = cmd.formats.then(|| hashes::Writes::start(crate_dir));
+    let spawned = command.spawn();
+    // The output from a terminal only ends when every copy of the command's end of it is closed
+    drop(command);
+    let outcome = spawned.and_then(|mut child| {
+        let forwarders = match (terminal, by_line) {
+            (Some(terminal), true) => vec![Some(forward(terminal, false, sink.clone()))],
+            (Some(terminal), false) => vec![Some(copy_output(terminal))],
+            (None, true) => vec![
+                child.stdout.take().map(|out| forward(out, false, sink.clone())),
+                child.stderr.take().map(|err| forward(err, true, sink.clone())),
+            ],
+            (None, false) => Vec::new(),
+        };
+        let outcome = wait(child, cmd, cancel);
+        for forwarder in forwarders.into_iter().flatten() {
+            let _ = forwarder.join();
+        }
+        outcome
+    });
+    if let Some(writes) = writes {
+        writes.finish();
+    }
+    let messages = messages.map(|messages| std::mem::take(&mut *messages.lock().expect("Messages poisoned")));
+    let built = messages.as_ref().and_then(|messages| messages.built);
+    if let Some(summary) = messages.as_ref().and_then(|messages| summarize(&messages.diagnostics)) {
+        log::info!("The {} command reported {}", cmd.name, summary);
+    }
+    if let Some(tests) = messages.as_ref().map(|messages| &messages.tests) {
+        report_tests(&cmd.name, tests);
+    }
+    if let Some(checks) = messages.as_ref().map(|messages| &messages.checks) {
+        match deny::summarize(checks) {
+            Some(findings) if deny::has_errors(checks) => log::error!("The {} command found {}", cmd.name, findings),
+            Some(findings) => log::warn!("The {} command found {}", cmd.name, findings),
+            None => {},
+        }
+    }
+    let status = match &outcome {
+        Ok(Outcome::Exited(status)) if status.success() => Some(Status::Passed),
+        Ok(Outcome::Exited(_)) if built == Some(true) && cmd.runs_tests() => Some(Status::TestsFailed),
+        Ok(Outcome::Exited(status)) if status.code() == Some(nextest::TESTS_FAILED) && cmd.runs_nextest() => {
+            Some(Status::TestsFailed)
+        },
+        Ok(Outcome::Exited(_)) | Ok(Outcome::TimedOut(_)) | Err(_) => Some(Status::Failed),
+        Ok(Outcome::Cancelled) => None,
+    };
+    if let Some(captured) = &sink.captured {
+        match status {
+            Some(Status::Passed) => println!("\u{2713} {}", cmd.name),
+            Some(_) => {
+                println!("\u{2717} {}", cmd.name);
+                let lines = captured.lock().expect("Captured output poisoned");
+                print_lines(&lines, &cmd.name, prefixed);
+            },
+            None => {},
+        }
+    }
+    match outcome {
+        Ok(Outcome::Exited(exit)) => {
+            if exit.success() {
+                log::debug!("Successfully executed {}", description);
+            } else {
+                log::error!("Failed to execute {}: Returned status {:?}", description, exit.code());
+                match (built, status) {
+                    (Some(true), Some(Status::TestsFailed)) => {
+                        log::info!("The {} command compiled, so the commands after it still run", cmd.name)
+                    },
+                    (Some(false), _) => log::error!("The {} command failed to compile", cmd.name),
+                    _ => {},
+                }
+            }
+            status
+        },
+        Ok(Outcome::Cancelled) => {
+            log::info!("Stopped the {} command", cmd.name);
+            None
+        },
+        Ok(Outcome::TimedOut(timeout)) => {
+            log::error!("Killed the {} command after it ran for longer than {:?}", cmd.name, timeout);
+            status
+        },
+        Err(e) => {
+            log::error!("Failed to execute {}: {:?}", description, e);
+            status
+        },
+    }
+}
+
+/// Run the command in a systemd scope of its own if it has a memory or CPU limit, which puts it in a cgroup with them
+///
+/// systemd-run starts the command itself, so it keeps its process id and group. Without a systemd user session the
+/// command runs without the limits.
+#[cfg(target_os = "linux")]
+fn limit_resources(cmd: &Command, args: Vec<String>) -> Vec<String> {
+    if (cmd.memory_limit.is_none() && cmd.cpu_limit.is_none()) || !systemd_scopes() {
+        return args;
+    }
+    let scope = ["systemd-run", "--user", "--scope", "--quiet", "--collect"];
+    let mut limited: Vec<String> = scope.map(String::from).into();
+    if let Some(memory_limit) = cmd.memory_limit {
+        // Swapping instead of stopping at the limit would slow down the machine just the same
+        limited.extend(["-p".into(), format!("MemoryMax={}", memory_limit)]);
+        limited.extend(["-p".into(), "MemorySwapMax=0".into()]);
+    }
+    if let Some(cpu_limit) = cmd.cpu_limit {
+        let percent = (cpu_limit * 100.0).round().max(1.0) as u64;
+        limited.extend(["-p".into(), format!("CPUQuota={}%", percent)]);
+    }
+    limited.push("--".into());
+    limited.extend(args);
+    limited
+}
+
+/// Check once if systemd can start scopes for the user, warning if it can't since the limits are left out then
+#[cfg(target_os = "linux")]
+fn systemd_scopes() -> bool {
+    static AVAILABLE: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let available = std::process::Command::new("systemd-run")
+            .args(["--user", "--scope", "--quiet", "true"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if !available {
+            log::warn!("The memory and CPU limits need systemd-run and a systemd user session, running without them");
+        }
+        available
+    })
+}
+
+/// The limits are set on the job object of the command on Windows, and aren't supported anywhere else
+#[cfg(not(target_os = "linux"))]
+fn limit_resources(cmd: &Command, args: Vec<String>) -> Vec<String> {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    let limited = cmd.memory_limit.is_some() || cmd.cpu_limit.is_some();
+    if !cfg!(windows) && limited && !WARNED.swap(true, Ordering::Relaxed) {
+        log::warn!("The memory and CPU limits are only supported on Linux and Windows, running without them");
+    }
+    args
+}
+
+/// Start the command with the niceness, and the idle I/O priority on Linux, so it doesn't slow down the editor
+#[cfg(unix)]
+fn lower_priority(command: &mut std::process::Command, nice: u8) {
+    let nice = libc::c_int::from(nice);
+    let set_priority = move || {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // This is only a hint, so an older kernel without it is fine
+        #[cfg(target_os = "linux")]
+        unsafe {
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+            libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << 13);
+        }
+        Ok(())
+    };
+    // Both are safe to call between fork and exec, since they are plain system calls
+    unsafe { std::os::unix::process::CommandExt::pre_exec(command, set_priority) };
+}
+
+/// Print the lines from the output of a command, in a thread of its own until the output is closed
+///
+/// The lines are printed after the name of the command if the sink is prefixed, where the lines from stderr also have
+/// the name of the stream, so they can be told apart when filtering the output. If the sink captures the output the
+/// lines are kept there instead of being printed. The JSON messages from cargo are replaced by the diagnostics in them,
+/// which go to stderr like they do without JSON.
+fn forward<R: Read + Send + 'static>(output: R, stderr: bool, sink: Sink) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).split(b'\n') {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            let line = String::from_utf8_lossy(&line).trim_end_matches('\r').to_string();
+            let lines = match (&sink.messages, cargo::parse_message(&line)) {
+                (Some(messages), Some(message)) => {
+                    let mut messages = messages.lock().expect("Messages poisoned");
+                    match message {
+                        cargo::Message::Diagnostic(diagnostic) if messages.diagnostics.contains(&diagnostic) => {
+                            Vec::new()
+                        },
+                        cargo::Message::Diagnostic(diagnostic) => {
+                            let lines = diagnostic.rendered.lines().map(|line| (true, line.into())).collect();
+                            messages.diagnostics.push(diagnostic);
+                            lines
+                        },
+                        cargo::Message::BuildFinished(success) => {
+                            messages.built = Some(success);
+                            Vec::new()
+                        },
+                        cargo::Message::Other => Vec::new(),
+                    }
+                },
+                (Some(messages), None) => match (nextest::parse_event(&line), deny::parse_line(&line)) {
+                    (Some(test), _) => {
+                        messages.lock().expect("Messages poisoned").tests.extend(test);
+                        Vec::new()
+                    },
+                    (None, Some(deny::Line::Diagnostic(lines))) => lines.into_iter().map(|line| (true, line)).collect(),
+                    (None, Some(deny::Line::Summary(checks))) => {
+                        messages.lock().expect("Messages poisoned").checks = checks;
+                        Vec::new()
+                    },
+                    (None, Some(deny::Line::Other)) => Vec::new(),
+                    (None, None) => vec![(stderr, line)],
+                },
+                _ => vec![(stderr, line)],
+            };
+            match &sink.captured {
+                Some(captured) => captured.lock().expect("Captured output poisoned").extend(lines),
+                None => print_lines(&lines, &sink.name, sink.prefixed),
+            }
+        }
+    })
+}
+
+/// Sum up the errors and warnings that point to the code, like `2 errors and 1 warning`, or None if there are none
+fn summarize(diagnostics: &[cargo::Diagnostic]) -> Option<String> {
+    let count = |level: &str| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.has_location() && diagnostic.level == level)
+            .count()
+    };
+    let counts: Vec<String> = [("error", count("error")), ("warning", count("warning"))]
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(level, count)| format!("{} {}{}", count, level, if *count == 1 { "" } else { "s" }))
+        .collect();
+    match counts.is_empty() {
+        true => None,
+        false => Some(counts.join(" and ")),
+    }
+}
+
+/// Log how many of the tests cargo nextest ran passed, which of them failed and which ran the longest
+fn report_tests(name: &str, tests: &[nextest::TestResult]) {
+    let summary = match nextest::summarize(tests) {
+        Some(summary) => summary,
+        None => return,
+    };
+    log::info!("The {} command ran {}", name, summary);
+    let failed = nextest::failed(tests);
+    if !failed.is_empty() {
+        log::error!("Failed tests: {}", failed.join(", "));
+    }
+    let slowest = nextest::slowest(tests, SLOWEST_TESTS);
+    if !slowest.is_empty() {
+        log::info!("Slowest tests: {}", slowest.join(", "));
+    }
+}
+
+/// Print lines of output from a command to the stream they were written to, after the name of the command if
+/// `prefixed`
+fn print_lines(lines: &[(bool, String)], name: &str, prefixed: bool) {
+    for (stderr, line) in lines {
+        let prefix = match (prefixed, stderr) {
+            (false, _) => String::new(),
+            (true, true) => format!("[{}:err] ", name),
+            (true, false) => format!("[{}] ", name),
+        };
+        if *stderr {
+            eprintln!("{}{}", prefix, line);
+        } else {
+            println!("{}{}", prefix, line);
+        }
+    }
+}
+
+/// Copy the output of a command to stdout as it comes, in a thread of its own until the output is closed
+///
+/// Nothing waits for the end of the line, since progress bars are redrawn without one.
+fn copy_output<R: Read + Send + 'static>(mut output: R) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut stdout = std::io::stdout();
+        loop {
+            let len = match output.read(&mut buf) {
+                Ok(0) => return,
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                // Reading a terminal that the command closed fails instead of returning 0
+                Err(_) => return,
+            };
+            if stdout.write_all(&buf[..len]).and_then(|_| stdout.flush()).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+/// Open a pseudo-terminal for a command, returns our end of it followed by the command's stdout and stderr
+///
+/// The terminal has the size of the one auto-check-rs runs in, or 120 columns when it doesn't run in one.
+#[cfg(unix)]
+fn open_pty() -> std::io::Result<(std::fs::File, std::fs::File, std::fs::File)> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let mut size = libc::winsize {
+        ws_row: 40,
+        ws_col: 120,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    let (mut ours, mut theirs) = (0, 0);
+    // The size is only read, but it is a mutable pointer on some platforms
+    let size = std::ptr::addr_of_mut!(size);
+    let result = unsafe { libc::openpty(&mut ours, &mut theirs, std::ptr::null_mut(), std::ptr::null_mut(), size) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let (ours, theirs) = unsafe { (std::fs::File::from_raw_fd(ours), std::fs::File::from_raw_fd(theirs)) };
+    // Only the command's stdout and stderr should keep the terminal open, not copies it inherits
+    for file in [&ours, &theirs] {
+        unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    let err = theirs.try_clone()?;
+    Ok((ours, theirs, err))
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> std::io::Result<(std::fs::File, std::fs::File, std::fs::File)> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Stop every command that is running, together with the processes they started
+///
+/// This is used when quitting, since the commands are in other process groups and don't get the Ctrl-C.
+#[cfg(unix)]
+pub fn stop_all() {
+    let groups: Vec<libc::pid_t> = RUNNING
+        .lock()
+        .expect("Running commands poisoned")
+        .iter()
+        .map(|&id| -(id as libc::pid_t))
+        .collect();
+    if groups.is_empty() {
+        return;
+    }
+    STOPPING.store(true, Ordering::Relaxed);
+    log::info!("Stopping the running commands");
+    for &group in groups.iter() {
+        unsafe { libc::kill(group, libc::SIGTERM) };
+    }
+
+    // The runner threads reap the commands, and a group is gone once everything in it has exited
+    let started = Instant::now();
+    while started.elapsed() < TERMINATE_GRACE {
+        if groups.iter().all(|&group| unsafe { libc::kill(group, 0) } != 0) {
+            return;
+        }
+        std::thread::sleep(CANCEL_POLL);
+    }
+    log::warn!("The commands didn't stop within {:?}, killing them", TERMINATE_GRACE);
+    for &group in groups.iter() {
+        unsafe { libc::kill(group, libc::SIGKILL) };
+    }
+}
+
+/// Stop every command that is running, together with the processes they started
+///
+/// Ctrl-C reaches the commands as well on Windows, but a job object also stops the processes that ignore it.
+#[cfg(windows)]
+pub fn stop_all() {
+    let jobs = JOBS.lock().expect("Running jobs poisoned");
+    if jobs.is_empty() {
+        return;
+    }
+    STOPPING.store(true, Ordering::Relaxed);
+    log::info!("Stopping the running commands");
+    for (_, job) in jobs.iter() {
+        job.terminate();
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn stop_all() {}
+
+/// A job object holding the processes of a command, which kills all of them when it is closed
+///
+/// This is what a process group is on Unix, and closing it when auto-check-rs exits, even when it is killed, means
+/// nothing the command started is left running.
+#[cfg(windows)]
+struct Job(windows_sys::Win32::Foundation::HANDLE);
+
+#[cfg(windows)]
+impl Job {
+    /// Create a job that kills its processes when it is closed, with the limits of the command, and put the child in it
+    ///
+    /// The child has already started, but cargo only starts the compiler and the tests some time after that.
+    fn assign(child: &Child, cmd: &Command) -> std::io::Result<Job> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::System::JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JobObjectCpuRateControlInformation,
+            JobObjectExtendedLimitInformation, SetInformationJobObject, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE,
+            JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOB_OBJECT_LIMIT_JOB_MEMORY, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        };
+
+        let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+        if handle == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let job = Job(handle);
+        let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        if let Some(memory_limit) = cmd.memory_limit {
+            info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+            info.JobMemoryLimit = memory_limit as usize;
+        }
+        let mut set = unsafe {
+            SetInformationJobObject(
+                handle,
+                JobObjectExtendedLimitInformation,
+                &info as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const std::ffi::c_void,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        };
+        if let Some(cpu_limit) = cmd.cpu_limit.filter(|_| set != 0) {
+            // The rate is in hundredths of a percent of all the CPUs together
+            let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get()) as f64;
+            let mut rate: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = unsafe { std::mem::zeroed() };
+            rate.ControlFlags = JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+            rate.Anonymous.CpuRate = (cpu_limit / cpus * 10000.0).round().clamp(1.0, 10000.0) as u32;
+            set = unsafe {
+                SetInformationJobObject(
+                    handle,
+                    JobObjectCpuRateControlInformation,
+                    &rate as *const JOBOBJECT_CPU_RATE_CONTROL_INFORMATION as *const std::ffi::c_void,
+                    std::mem::size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as u32,
+                )
+            };
+        }
+        if set == 0 || unsafe { AssignProcessToJobObject(handle, child.as_raw_handle() as _) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(job)
+    }
+
+    /// Kill every process in the job
+    fn terminate(&self) {
+        unsafe { windows_sys::Win32::System::JobObjects::TerminateJobObject(self.0, 1) };
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+    }
+}
+
+/// Wait for the child running the command to exit, keeping track of it while it runs
+///
+/// The child is stopped if the run is cancelled or the timeout of the command is reached first.
+fn wait(mut child: Child, cmd: &Command, cancel: Option<&AtomicBool>) -> std::io::Result<Outcome> {
+    let timeout = cmd.timeout;
+    let id = child.id();
+    RUNNING.lock().expect("Running commands poisoned").push(id);
+    #[cfg(windows)]
+    match Job::assign(&child, cmd) {
+        Ok(job) => JOBS.lock().expect("Running jobs poisoned").push((id, job)),
+        Err(e) => log::warn!("Failed to create a job object, the processes the command starts may be left: {}", e),
+    }
+    let status = match (cancel, timeout) {
+        (None, None) => child.wait().map(Outcome::Exited),
+        _ => wait_or_stop(&mut child, cancel, timeout),
+    };
+    RUNNING.lock().expect("Running commands poisoned").retain(|&running| running != id);
+    // Closing the job kills anything the command left behind, like a test binary it didn't wait for
+    #[cfg(windows)]
+    JOBS.lock().expect("Running jobs poisoned").retain(|(running, _)| *running != id);
+    // The thread that is quitting exits the process once everything is stopped
+    while STOPPING.load(Ordering::Relaxed) {
+        std::thread::park();
+    }
+    status
+}
+
+fn wait_or_stop(child: &mut Child, cancel: Option<&AtomicBool>, timeout: Option<Duration>) -> std::io::Result<Outcome> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Outcome::Exited(status));
+        }
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            terminate(child)?;
+            return Ok(Outcome::Cancelled);
+        }
+        if let Some(timeout) = timeout.filter(|&timeout| started.elapsed() >= timeout) {
+            terminate(child)?;
+            return Ok(Outcome::TimedOut(timeout));
+        }
+        std::thread::sleep(CANCEL_POLL);
+    }
+}
+
+/// Ask the process group of the child to exit, and kill it if it is still there after the grace period
+#[cfg(unix)]
+fn terminate(child: &mut Child) -> std::io::Result<()> {
+    let group = -(child.id() as libc::pid_t);
+    // The group is gone if the child and everything it started have exited already
+    unsafe { libc::kill(group, libc::SIGTERM) };
+
+    let started = Instant::now();
+    while started.elapsed() < TERMINATE_GRACE {
+        if child.try_wait()?.is_some() {
+            // Anything the child left behind in the group doesn't get the rest of the grace period
+            unsafe { libc::kill(group, libc::SIGKILL) };
+            return Ok(());
+        }
+        std::thread::sleep(CANCEL_POLL);
+    }
+
+    log::warn!("The command didn't stop within {:?}, killing it", TERMINATE_GRACE);
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    child.wait().map(|_| ())
+}
+
+#[cfg(windows)]
+fn terminate(child: &mut Child) -> std::io::Result<()> {
+    let id = child.id();
+    let jobs = JOBS.lock().expect("Running jobs poisoned");
+    match jobs.iter().find(|(running, _)| *running == id) {
+        Some((_, job)) => job.terminate(),
+        None => child.kill()?,
+    }
+    drop(jobs);
+    child.wait().map(|_| ())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminate(child: &mut Child) -> std::io::Result<()> {
+    child.kill()?;
+    child.wait().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(name: &str, program: &str, parallel: bool, depends_on: &[&str]) -> Command {
+        let mut cmd = Command::named(name, vec![program.into()]);
+        cmd.parallel = parallel;
+        cmd.depends_on = depends_on.iter().map(|name| name.to_string()).collect();
+        cmd
+    }
+
+    #[test]
+    fn batches_of_parallel_commands() {
+        let commands = [
+            command("fmt", "true", false, &[]),
+            command("clippy", "true", true, &[]),
+            command("test", "true", true, &[]),
+            command("doc", "true", false, &[]),
+            command("audit", "true", true, &[]),
+        ];
+        let names: Vec<Vec<&str>> = batches(&commands)
+            .iter()
+            .map(|batch| batch.iter().map(|cmd| cmd.name.as_str()).collect())
+            .collect();
+        assert_eq!(names, [vec!["fmt"], vec!["clippy", "test"], vec!["doc"], vec!["audit"]]);
+    }
+
+    /// A diamond where `check` comes first, `clippy` and `test` depend on it, and `doc` depends on both of them
+    fn diamond(clippy: &str) -> Vec<Command> {
+        vec![
+            command("check", "true", false, &[]),
+            command("clippy", clippy, false, &["check"]),
+            command("test", "true", false, &["check"]),
+            command("doc", "true", false, &["clippy", "test"]),
+        ]
+    }
+
+    fn statuses(commands: &[Command], keep_going: bool) -> Vec<Status> {
+        let results = run_graph(commands, Path::new("."), None, keep_going).expect("The run was stopped");
+        results.into_iter().map(|(status, _)| status).collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn diamond_passes() {
+        assert_eq!(statuses(&diamond("true"), false), [Status::Passed; 4]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn diamond_skips_after_failure() {
+        use Status::*;
+        assert_eq!(statuses(&diamond("false"), true), [Passed, Failed, Passed, Skipped]);
+    }
+}
@@ -0,0 +1,1116 @@
+use crate::cargo::{self, CargoOptions};
+use crate::cli::Args;
+use crate::command::{Command, CommandConfig};
+use crate::config::{self, Config};
+use crate::ignores::{self, Ignores};
+use crate::nextest;
+use crate::remote::{self, Container, Ssh};
+use crate::routes::Route;
+use ignore::gitignore::GitignoreBuilder;
+use ignore::overrides::{Override, OverrideBuilder};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A set of defaults for the built-in commands for a common workflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Only run cargo check
+    Fast,
+    /// Check formatting and deny warnings from clippy in addition to the normal commands
+    Strict,
+    /// Run the same commands as strict once, and exit with the status of the run
+    Ci,
+}
+
+/// When to run after a change is detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Debounce {
+    /// Run the delay after the first change, including any other changes made until then
+    Fixed,
+    /// Run as soon as something changes, and collect the changes made within the delay after that for the next run
+    Leading,
+    /// Wait until nothing has changed for the delay, so a burst of changes gives a single run
+    #[serde(alias = "trailing")]
+    #[value(alias = "trailing")]
+    Quiet,
+}
+
+/// When to use colors, both in the log and in the output of the commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Color {
+    /// Leave it to the commands, which use colors when they write to a terminal
+    Auto,
+    /// Use colors even when the output is piped, like into less -R
+    Always,
+    /// Never use colors
+    Never,
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = clap::ValueEnum::to_possible_value(self).expect("No skipped values");
+        f.write_str(value.get_name())
+    }
+}
+
+/// The interval in milliseconds for the poll backend when --poll isn't given
+const DEFAULT_POLL: u64 = 1000;
+
+/// The file that starts a run when it is created or touched, unless another one is configured
+const DEFAULT_TRIGGER_FILE: &str = ".auto-check-trigger";
+
+/// Changes to files larger than this never trigger a run by default
+const DEFAULT_MAX_FILE_SIZE: Size = Size(10 << 20);
+
+/// How the file system is watched for changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// The native backend for the platform
+    Auto,
+    /// inotify on Linux
+    Inotify,
+    /// FSEvents on macOS
+    Fsevents,
+    /// kqueue on the BSDs, and on macOS when built with the macos_kqueue feature
+    Kqueue,
+    /// ReadDirectoryChangesW on Windows
+    Windows,
+    /// Check for changes at an interval, the same as --poll
+    Poll,
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = clap::ValueEnum::to_possible_value(self).expect("No skipped values");
+        f.write_str(value.get_name())
+    }
+}
+
+/// How often a scheduled command runs, written as a number of seconds or with an s, m, h, d or w suffix like 24h
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "IntervalValue")]
+pub struct Interval(pub Duration);
+
+/// An interval in the config file can be either a number of seconds or a string with a suffix
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntervalValue {
+    Secs(u64),
+    Text(String),
+}
+
+/// The units an interval can be written in, from the largest
+const INTERVAL_UNITS: &[(char, u64)] = &[('w', 7 * 24 * 3600), ('d', 24 * 3600), ('h', 3600), ('m', 60), ('s', 1)];
+
+impl TryFrom<IntervalValue> for Interval {
+    type Error = String;
+
+    fn try_from(value: IntervalValue) -> Result<Interval, String> {
+        match value {
+            IntervalValue::Secs(0) => Err("invalid interval 0, it has to be at least a second".into()),
+            IntervalValue::Secs(secs) => Ok(Interval(Duration::from_secs(secs))),
+            IntervalValue::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl std::str::FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Interval, String> {
+        let s = s.trim();
+        let lower = s.to_lowercase();
+        let (number, unit) = match lower.char_indices().last() {
+            Some((i, suffix)) => match INTERVAL_UNITS.iter().find(|(unit, _)| *unit == suffix) {
+                Some((_, secs)) => (&lower[..i], *secs),
+                None => (&lower[..], 1),
+            },
+            None => (&lower[..], 1),
+        };
+        number
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .filter(|&secs| secs > 0)
+            .map(|secs| Interval(Duration::from_secs(secs)))
+            .ok_or_else(|| format!("invalid interval {:?}, expected a number of seconds or something like 24h", s))
+    }
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        match INTERVAL_UNITS.iter().find(|(_, unit)| secs.is_multiple_of(*unit)) {
+            Some((suffix, unit)) if secs > 0 => write!(f, "{}{}", secs / unit, suffix),
+            _ => write!(f, "{}s", secs),
+        }
+    }
+}
+
+/// A file size in bytes, written as a number of bytes or with a K, M or G suffix like 10M
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "SizeValue")]
+pub struct Size(pub u64);
+
+/// A size in the config file can be either a number of bytes or a string with a suffix
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<SizeValue> for Size {
+    type Error = String;
+
+    fn try_from(value: SizeValue) -> Result<Size, String> {
+        match value {
+            SizeValue::Bytes(bytes) => Ok(Size(bytes)),
+            SizeValue::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl std::str::FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Size, String> {
+        let s = s.trim();
+        let upper = s.to_uppercase();
+        let number = upper.trim_end_matches("IB").trim_end_matches('B');
+        let (number, unit) = match number.char_indices().last() {
+            Some((i, 'K')) => (&number[..i], 1 << 10),
+            Some((i, 'M')) => (&number[..i], 1 << 20),
+            Some((i, 'G')) => (&number[..i], 1 << 30),
+            _ => (number, 1),
+        };
+        number
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit))
+            .map(Size)
+            .ok_or_else(|| format!("invalid size {:?}, expected a number of bytes or something like 10M", s))
+    }
+}
+
+impl std::fmt::Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            bytes if bytes >= 1 << 30 && bytes % (1 << 30) == 0 => write!(f, "{}G", bytes >> 30),
+            bytes if bytes >= 1 << 20 && bytes % (1 << 20) == 0 => write!(f, "{}M", bytes >> 20),
+            bytes if bytes >= 1 << 10 && bytes % (1 << 10) == 0 => write!(f, "{}K", bytes >> 10),
+            bytes => write!(f, "{}", bytes),
+        }
+    }
+}
+
+/// The settings after merging the command line with the config file
+///
+/// This is everything that can change when the config file is reloaded.
+pub struct Settings {
+    pub config_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub preset: Option<Preset>,
+    pub verbose: usize,
+    /// When to use colors in the log and in the output of the commands
+    pub color: Color,
+    /// Only print the output of the commands that fail
+    pub quiet: bool,
+    pub delay: Duration,
+    pub debounce: Debounce,
+    /// The longest time to wait after the first change, even if things keep changing
+    pub max_wait: Option<Duration>,
+    /// The shortest time from the start of one run to the start of the next, for the runs started by changes
+    pub min_interval: Option<Duration>,
+    /// Poll for changes with this interval instead of using the native file system events
+    pub poll: Option<Duration>,
+    /// The backend to watch the file system with when not polling
+    pub backend: Backend,
+    /// Watch the directories that symbolic links point to
+    pub follow_symlinks: bool,
+    /// The files and directories outside the crate directory that are also watched
+    pub watch: Vec<PathBuf>,
+    /// Only changes to files with these extensions trigger a run, or any file if empty
+    pub ext: Vec<String>,
+    /// Only changes to files tracked by git trigger a run
+    pub tracked_only: bool,
+    /// Changes to files larger than this never trigger a run
+    pub max_file_size: Option<Size>,
+    /// Creating or touching this file starts a run right away
+    pub trigger_file: PathBuf,
+    /// Only changes to paths in the crate directory matching the include globs trigger a run, or any path if empty
+    pub includes: Override,
+    /// The include globs from the command line or config file
+    pub include: Vec<String>,
+    /// Changes to paths in the crate directory matching the no-op globs don't run anything if nothing else changed
+    pub no_op: Override,
+    /// The no-op globs from the command line or config file
+    pub no_op_globs: Vec<String>,
+    pub run_first: bool,
+    pub once: bool,
+    /// Stop the running commands and start over when something changes
+    pub restart_on_change: bool,
+    /// Run the commands given as a line through the shell
+    pub shell: bool,
+    pub ignores: Ignores,
+    /// The patterns for the temporary files from editors that were added to the ignore rules
+    pub editor_ignore: Vec<String>,
+    /// The patterns from the config file and --exclude that were added to the ignore rules
+    pub ignore: Vec<String>,
+    pub commands: Vec<Command>,
+    /// The commands to run when Cargo.lock changes
+    pub lockfile_commands: Vec<Command>,
+    /// Fail the cargo commands when there are warnings
+    pub deny_warnings: bool,
+    /// The niceness the commands run with
+    pub nice: Option<u8>,
+    /// Run the commands in a terminal of their own
+    pub pty: bool,
+    /// Run the commands on another machine over SSH
+    pub ssh: Option<Ssh>,
+    /// Run the commands in a container
+    pub container: Option<Container>,
+    /// Print every line of output after the name of the command
+    pub prefix: bool,
+    /// The target directory the commands build in, if it is kept apart from the one of the workspace
+    pub isolated_target_dir: Option<PathBuf>,
+    /// Wait for other cargo processes to release their locks before running the commands
+    pub wait_for_lock: bool,
+    /// The commands to run after all the commands passed
+    pub on_success: Vec<Command>,
+    /// The commands to run after any of the commands failed
+    pub on_failure: Vec<Command>,
+    /// The commands to run every so often, whether anything changed or not
+    pub schedule: Vec<Command>,
+    /// Other commands to run for changes below some paths
+    pub routes: Vec<Route>,
+    /// Run the rest of the commands when one fails
+    pub keep_going: bool,
+    /// Only run the commands that failed until they pass
+    pub failed_first: bool,
+    /// Only build the packages in the workspace that the changes affect
+    pub affected_only: bool,
+    /// Only run the tests in the changed test files when nothing else changed
+    pub targeted_tests: bool,
+    /// Only run the doc tests when nothing but doc comments changed
+    pub doc_tests: bool,
+    /// Run all the tests after this many targeted runs in a row, or never if 0
+    pub full_test_every: u32,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Config(config::Error),
+    Ignore(String, ignore::Error),
+    Include(String, ignore::Error),
+    NoOp(String, ignore::Error),
+    Watch(PathBuf, std::io::Error),
+    CustomCmd(String, shell_words::ParseError),
+    UnknownCommand(String),
+    DependencyCycle(Vec<String>),
+    MissingCwd(String, PathBuf),
+    SshAndContainer,
+    NoCommands,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Config(e) => e.fmt(f),
+            Error::Ignore(pattern, e) => write!(f, "Invalid ignore pattern {:?}: {}", pattern, e),
+            Error::Include(pattern, e) => write!(f, "Invalid include pattern {:?}: {}", pattern, e),
+            Error::NoOp(pattern, e) => write!(f, "Invalid no-op pattern {:?}: {}", pattern, e),
+            Error::Watch(path, e) => write!(f, "Failed to watch {}: {}", path.to_string_lossy(), e),
+            Error::CustomCmd(cmd, e) => write!(f, "Failed to parse command {:?}: {}", cmd, e),
+            Error::UnknownCommand(name) => write!(f, "There is no command named {:?} in the pipeline", name),
+            Error::DependencyCycle(names) => write!(f, "The commands {} depend on each other", names.join(", ")),
+            Error::MissingCwd(name, cwd) => {
+                write!(f, "The directory {} for the {} command doesn't exist", cwd.to_string_lossy(), name)
+            },
+            Error::SshAndContainer => write!(f, "The commands can't run both over SSH and in a container"),
+            Error::NoCommands => write!(f, "Cowardly refusing to start because there is no commands to run"),
+        }
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(e: config::Error) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl Settings {
+    /// Load the config file from the crate directory and merge it with the command line
+    pub fn load(args: &Args, crate_dir: &Path) -> Result<Settings, Error> {
+        let (mut config, config_path) = Config::load(crate_dir)?;
+
+        let profile = match &args.profile {
+            Some(profile) => Some(profile.clone()),
+            None => config::env_var("PROFILE")?,
+        };
+        if let Some(profile) = &profile {
+            config.apply_profile(profile)?;
+        }
+
+        config.apply_env()?;
+
+        let verbose = match args.verbose {
+            0 => config.verbose.unwrap_or(0),
+            n => n as usize,
+        };
+
+        let delay_ms = args.delay.or(config.delay).unwrap_or(1000);
+
+        let debounce = args.debounce.or(config.debounce).unwrap_or(Debounce::Fixed);
+
+        let max_wait = args.max_wait.or(config.max_wait).map(Duration::from_millis);
+        let min_interval = args
+            .min_interval
+            .or(config.min_interval)
+            .filter(|&min_interval| min_interval > 0)
+            .map(Duration::from_millis);
+
+        let backend = args.backend.or(config.backend).unwrap_or(Backend::Auto);
+        let poll = args
+            .poll
+            .or(config.poll)
+            .or_else(|| (backend == Backend::Poll).then_some(DEFAULT_POLL))
+            .map(Duration::from_millis);
+        let follow_symlinks = args.follow_symlinks || config.follow_symlinks.unwrap_or(false);
+
+        // The paths from the command line are relative to the current directory, and the others to the crate directory
+        let watch = if args.watch.is_empty() {
+            config.watch.iter().map(|path| crate_dir.join(path)).collect()
+        } else {
+            args.watch.clone()
+        };
+        let watch = watch
+            .into_iter()
+            .map(|path| path.canonicalize().map_err(|e| Error::Watch(path, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let ext = if args.ext.is_empty() { &config.ext } else { &args.ext };
+        let ext = ext.iter().map(|ext| ext.trim_start_matches('.').to_string()).collect();
+        let tracked_only = args.tracked_only || config.tracked_only.unwrap_or(false);
+        let max_file_size = Some(args.max_file_size.or(config.max_file_size).unwrap_or(DEFAULT_MAX_FILE_SIZE))
+            .filter(|size| size.0 > 0);
+        let trigger_file = match (&args.trigger_file, &config.trigger_file) {
+            (Some(path), _) => absolute_path(path),
+            (None, Some(path)) => absolute_path(&crate_dir.join(path)),
+            (None, None) => crate_dir.join(DEFAULT_TRIGGER_FILE),
+        };
+
+        let include = if args.include.is_empty() { config.include.clone() } else { args.include.clone() };
+        let includes = {
+            let mut builder = OverrideBuilder::new(crate_dir);
+            for pattern in include.iter() {
+                builder.add(pattern).map_err(|e| Error::Include(pattern.clone(), e))?;
+            }
+            builder.build().map_err(|e| Error::Include(include.join(" "), e))?
+        };
+        let no_op_globs = if args.no_op.is_empty() { config.no_op.clone() } else { args.no_op.clone() };
+        let no_op = {
+            let mut builder = OverrideBuilder::new(crate_dir);
+            for pattern in no_op_globs.iter() {
+                builder.add(pattern).map_err(|e| Error::NoOp(pattern.clone(), e))?;
+            }
+            builder.build().map_err(|e| Error::NoOp(no_op_globs.join(" "), e))?
+        };
+
+        let run_first = !args.no_run_first && config.run_first.unwrap_or(true);
+
+        let preset = args.preset.or(config.preset);
+        let full = preset != Some(Preset::Fast);
+        let strict = preset == Some(Preset::Strict) || preset == Some(Preset::Ci);
+        let once = args.once || config.once.unwrap_or(preset == Some(Preset::Ci));
+        let restart_on_change = args.restart_on_change || config.restart_on_change.unwrap_or(false);
+
+        // The excluded patterns are layered on top of the ones from the config file instead of replacing them
+        config.ignore.extend(args.exclude.iter().cloned());
+
+        let editor_ignore = match config.editor_ignore.take() {
+            Some(patterns) => patterns,
+            None => ignores::EDITOR_PATTERNS.iter().map(|pattern| pattern.to_string()).collect(),
+        };
+
+        let ignores = {
+            let mut builder = GitignoreBuilder::new(crate_dir);
+            // The .git directory is currently not ignored, and
+            // there is no way of initializing it like git would yet.
+            // See: https://github.com/BurntSushi/ripgrep/issues/1040
+            builder
+                .add_line(None, "**/.git")
+                .expect("Failed to add .git to ignore list");
+            // The editor files come first, so they can be allowed again using the ignore list
+            for pattern in editor_ignore.iter().chain(config.ignore.iter()) {
+                builder
+                    .add_line(config_path.clone(), pattern)
+                    .map_err(|e| Error::Ignore(pattern.clone(), e))?;
+            }
+            Ignores::new(crate_dir, builder.build().expect("Failed to build the ignore rules"))
+        };
+
+        let cargo = CargoOptions {
+            features: if args.features.is_empty() { config.features.clone() } else { args.features.clone() },
+            all_features: args.all_features || config.all_features.unwrap_or(false),
+            no_default_features: args.no_default_features || config.no_default_features.unwrap_or(false),
+            release: args.release || (args.cargo_profile.is_none() && config.release.unwrap_or(false)),
+            profile: match (&args.cargo_profile, args.release) {
+                (Some(profile), _) => Some(profile.clone()),
+                (None, true) => None,
+                (None, false) => config.cargo_profile.clone(),
+            },
+            target: args.target.clone().or_else(|| config.target.clone()),
+        };
+
+        // Tests for targets that can't run natively are run through cross instead of cargo when enabled
+        let test_program = match (&cargo.target, args.cross || config.cross.unwrap_or(false)) {
+            (Some(target), true) if cargo::host_triple().as_ref() != Some(target) => "cross",
+            _ => "cargo",
+        };
+
+        let shell = args.shell || config.shell.unwrap_or(false);
+        let tiered = args.tiered || config.tiered.unwrap_or(false);
+        let mut commands: Vec<Command> = Vec::new();
+
+        if !args.cmd.is_empty() {
+            for cmd in args.cmd.iter() {
+                commands.push(Command::parse(cmd, shell).map_err(|e| Error::CustomCmd(cmd.into(), e))?);
+            }
+        } else if let Some(config_commands) = &config.commands {
+            for cmd in config_commands {
+                commands.push(cmd.to_command(shell).map_err(|e| Error::CustomCmd(format!("{:?}", cmd), e))?);
+            }
+        } else {
+            let fix_fmt = args.fix_fmt || (!args.no_fmt && config.fix_fmt.unwrap_or(false));
+            if fix_fmt {
+                let mut fmt = Command::new(vec!["cargo".into(), "fmt".into(), "--all".into()]);
+                fmt.formats = true;
+                commands.push(fmt);
+            } else if args.fmt || (!args.no_fmt && config.fmt.unwrap_or(strict)) {
+                commands.push(Command::new(vec![
+                    "cargo".into(),
+                    "fmt".into(),
+                    "--all".into(),
+                    "--".into(),
+                    "--check".into(),
+                ]));
+            }
+
+            if !args.no_check && config.check.unwrap_or(true) {
+                let mut check = Command::new(vec!["cargo".into(), "check".into()]);
+                check.args.extend(cargo.args());
+                check.args.extend(split_args(args.check_args.as_ref().or(config.check_args.as_ref()))?);
+                commands.push(check);
+            }
+
+            if args.build || config.build.unwrap_or(false) {
+                let mut build = Command::new(vec!["cargo".into(), "build".into()]);
+                build.args.extend(cargo.args());
+                build.args.extend(split_args(args.build_args.as_ref().or(config.build_args.as_ref()))?);
+                commands.push(build);
+            }
+
+            if !args.no_clippy && config.clippy.unwrap_or(full) {
+                let mut clippy = Command::new(vec!["cargo".into(), "clippy".into(), "--all-targets".into()]);
+                clippy.args.extend(cargo.args());
+                clippy.args.extend(split_args(args.clippy_args.as_ref().or(config.clippy_args.as_ref()))?);
+                if strict {
+                    clippy.push_tool_args(vec!["-D".into(), "warnings".into()]);
+                }
+                clippy.slow = tiered;
+                commands.push(clippy);
+            }
+
+            if !args.no_test && config.test.unwrap_or(full) {
+                // Tests that run through cross keep using cargo test, which is the one cross supports
+                let nextest = test_program == "cargo"
+                    && (args.nextest || (!args.no_nextest && config.nextest.unwrap_or_else(nextest::installed)));
+                let mut test = match nextest {
+                    true => Command::named("test", vec!["cargo".into(), "nextest".into(), "run".into()]),
+                    false => Command::named("test", vec![test_program.into(), "test".into()]),
+                };
+                // The results of the tests are only written as JSON when asked for, since the format is experimental
+                if nextest {
+                    test.env.push(("NEXTEST_EXPERIMENTAL_LIBTEST_JSON".into(), "1".into()));
+                }
+                // The --profile of nextest is its own, so the one of cargo is passed with --cargo-profile
+                test.args.extend(cargo.args().into_iter().map(|arg| match nextest && arg == "--profile" {
+                    true => "--cargo-profile".into(),
+                    false => arg,
+                }));
+                test.args.extend(split_args(args.test_args.as_ref().or(config.test_args.as_ref()))?);
+                test.slow = tiered;
+                commands.push(test);
+            }
+
+            if args.doc || config.doc.unwrap_or(false) {
+                let mut doc = Command::new(vec!["cargo".into(), "doc".into(), "--no-deps".into()]);
+                doc.args.extend(cargo.args());
+                doc.args.extend(split_args(args.doc_args.as_ref().or(config.doc_args.as_ref()))?);
+                if strict {
+                    doc.deny_doc_warnings();
+                }
+                doc.slow = tiered;
+                commands.push(doc);
+            }
+
+            if let Some(toolchain) = args.toolchain.as_ref().or(config.toolchain.as_ref()) {
+                for cmd in commands.iter_mut() {
+                    cmd.set_toolchain(toolchain);
+                }
+            }
+        }
+
+        let custom_cmds = if args.custom_cmd.is_empty() { &config.custom_cmds } else { &args.custom_cmd };
+        for custom_cmd in custom_cmds {
+            commands.push(Command::parse(custom_cmd, shell).map_err(|e| Error::CustomCmd(custom_cmd.into(), e))?);
+        }
+
+        // Skip anything that was only whitespace instead of trying to run it
+        commands.retain(|cmd| !cmd.args.is_empty());
+        check_cwd(&commands, crate_dir)?;
+
+        for (name, env) in config.command_env.iter() {
+            let mut found = false;
+            for cmd in commands.iter_mut().filter(|cmd| &cmd.name == name) {
+                cmd.env.extend(env.iter().map(|(key, value)| (key.clone(), value.clone())));
+                found = true;
+            }
+            if !found {
+                return Err(Error::UnknownCommand(name.clone()));
+            }
+        }
+
+        if !args.trailing_args.is_empty() {
+            let mut found = false;
+            for cmd in commands.iter_mut().filter(|cmd| cmd.name == "test") {
+                cmd.push_tool_args(args.trailing_args.iter().cloned());
+                found = true;
+            }
+            if !found {
+                return Err(Error::UnknownCommand("test".into()));
+            }
+        }
+
+        check_dependencies(&commands)?;
+
+        let only = if args.only.is_empty() { &config.only } else { &args.only };
+        let skip = if args.skip.is_empty() { &config.skip } else { &args.skip };
+        if let Some(name) = only.iter().chain(skip.iter()).find(|name| !commands.iter().any(|cmd| &cmd.name == *name)) {
+            return Err(Error::UnknownCommand(name.clone()));
+        }
+        let selected = |cmd: &Command| (only.is_empty() || only.contains(&cmd.name)) && !skip.contains(&cmd.name);
+
+        // The names in the routes refer to the whole pipeline, and --only and --skip apply to them as well
+        let mut routes = Vec::new();
+        for route in config.routes.iter() {
+            let mut route_commands = Vec::new();
+            for name in route.only.iter() {
+                let cmd = commands
+                    .iter()
+                    .find(|cmd| &cmd.name == name)
+                    .ok_or_else(|| Error::UnknownCommand(name.clone()))?;
+                if selected(cmd) {
+                    route_commands.push(cmd.clone());
+                }
+            }
+            for cmd in route.commands.iter() {
+                route_commands.push(cmd.to_command(shell).map_err(|e| Error::CustomCmd(format!("{:?}", cmd), e))?);
+            }
+            check_cwd(&route_commands, crate_dir)?;
+            let path = crate_dir.join(&route.path);
+            routes.push(Route {
+                path: path.canonicalize().unwrap_or(path),
+                commands: route_commands,
+            });
+        }
+
+        commands.retain(selected);
+
+        if commands.is_empty() {
+            return Err(Error::NoCommands);
+        }
+
+        let mut lockfile_commands = to_commands(&config.lockfile_commands, shell)?;
+        let audit = args.audit || config.audit.unwrap_or(false);
+        if audit && !lockfile_commands.iter().any(|cmd| cmd.name == "audit") {
+            lockfile_commands.insert(0, Command::new(vec!["cargo".into(), "audit".into()]));
+        }
+        let deny = args.deny || config.deny.unwrap_or(false);
+        if deny && !lockfile_commands.iter().any(|cmd| cmd.name == "deny") {
+            let index = lockfile_commands.iter().position(|cmd| cmd.name == "audit").map_or(0, |index| index + 1);
+            lockfile_commands.insert(index, Command::new(vec!["cargo".into(), "deny".into(), "check".into()]));
+        }
+        let mut on_success = to_commands(&config.on_success, shell)?;
+        let mut on_failure = to_commands(&config.on_failure, shell)?;
+        let mut schedule = to_commands(&config.schedule, shell)?;
+
+        // A timeout of 0 turns off the one from the config file, and the one of a command turns off the default
+        let timeout = args.timeout.or(config.timeout).filter(|&timeout| timeout > 0).map(Duration::from_secs);
+        let route_commands = routes.iter_mut().flat_map(|route| route.commands.iter_mut());
+        let others = on_success.iter_mut().chain(on_failure.iter_mut()).chain(schedule.iter_mut());
+        for cmd in commands.iter_mut().chain(lockfile_commands.iter_mut()).chain(route_commands).chain(others) {
+            cmd.timeout = cmd.timeout.or(timeout).filter(|timeout| !timeout.is_zero());
+        }
+
+        // A limit of 0 turns off the one from the config file, the same as for the timeout
+        let memory_limit = args.memory_limit.or(config.memory_limit).map(|size| size.0).filter(|&size| size > 0);
+        let cpu_limit = args.cpu_limit.or(config.cpu_limit).filter(|&cpus| cpus > 0.0);
+        if memory_limit.is_some() || cpu_limit.is_some() {
+            let route_commands = routes.iter_mut().flat_map(|route| route.commands.iter_mut());
+            let others = on_success.iter_mut().chain(on_failure.iter_mut()).chain(schedule.iter_mut());
+            for cmd in commands.iter_mut().chain(lockfile_commands.iter_mut()).chain(route_commands).chain(others) {
+                cmd.memory_limit = cmd.memory_limit.or(memory_limit);
+                cmd.cpu_limit = cmd.cpu_limit.or(cpu_limit);
+            }
+        }
+
+        // A niceness of 0 is the normal priority, so it turns off the one from the config file
+        let nice = args.nice.or(config.nice).filter(|&nice| nice > 0);
+        let pty = args.pty || config.pty.unwrap_or(false);
+        let prefix = args.prefix || config.prefix.unwrap_or(false);
+        let quiet = args.quiet || config.quiet.unwrap_or(false);
+        let deny_warnings = args.deny_warnings || config.deny_warnings.unwrap_or(false);
+        let color = args.color.or(config.color).unwrap_or(Color::Auto);
+        let color_env: &[(&str, &str)] = match color {
+            Color::Auto => &[],
+            Color::Always => &[("CARGO_TERM_COLOR", "always"), ("CLICOLOR_FORCE", "1")],
+            Color::Never => &[("CARGO_TERM_COLOR", "never"), ("NO_COLOR", "1")],
+        };
+        // The jobs are set in the environment so they also apply to cargo when it is run by a custom command
+        let jobs = args.jobs.or(config.jobs).map(|jobs| jobs.to_string());
+        let route_commands = routes.iter_mut().flat_map(|route| route.commands.iter_mut());
+        let others = on_success.iter_mut().chain(on_failure.iter_mut()).chain(schedule.iter_mut());
+        for cmd in commands.iter_mut().chain(lockfile_commands.iter_mut()).chain(route_commands).chain(others) {
+            cmd.nice = nice;
+            cmd.pty = pty;
+            cmd.prefix = prefix;
+            cmd.quiet = quiet;
+            // Every cargo command gets the same flags, since changing them between the commands rebuilds everything
+            if deny_warnings && cmd.runs_cargo() {
+                cmd.deny_warnings();
+            }
+            // The environment of the command itself wins, the same as for the isolated target directory
+            for (key, value) in color_env {
+                if !cmd.env.iter().any(|(name, _)| name == key) {
+                    cmd.env.push((key.to_string(), value.to_string()));
+                }
+            }
+            if let Some(jobs) = &jobs {
+                cmd.env.push(("CARGO_BUILD_JOBS".into(), jobs.clone()));
+            }
+        }
+
+        // The hooks are left out, since they tell the user about the run and belong on this machine
+        let ssh = args.ssh.clone().or(config.ssh.clone()).map(|host| Ssh {
+            host,
+            dir: args
+                .remote_dir
+                .clone()
+                .or(config.remote_dir.clone())
+                .unwrap_or_else(|| crate_dir.to_string_lossy().into()),
+            sync: args.sync || config.sync.unwrap_or(false),
+        });
+        let container = match args.container.as_ref().or(config.container.as_ref()) {
+            Some(image) => Some(Container {
+                engine: match args.container_engine.as_ref().or(config.container_engine.as_ref()) {
+                    Some(engine) => engine.clone(),
+                    None => remote::container_engine(),
+                },
+                image: image.clone(),
+                args: split_args(args.container_args.as_ref().or(config.container_args.as_ref()))?,
+            }),
+            None => None,
+        };
+        if ssh.is_some() && container.is_some() {
+            return Err(Error::SshAndContainer);
+        }
+        if ssh.is_some() || container.is_some() {
+            let route_commands = routes.iter_mut().flat_map(|route| route.commands.iter_mut());
+            let others = lockfile_commands.iter_mut().chain(route_commands).chain(schedule.iter_mut());
+            for cmd in commands.iter_mut().chain(others) {
+                cmd.ssh = ssh.clone();
+                cmd.container = container.clone();
+            }
+        }
+
+        // Finding the target directory runs cargo metadata, so it is only done when it is needed
+        let isolated_target_dir = match args.isolated_target_dir || config.isolated_target_dir.unwrap_or(false) {
+            true => Some(isolated_target_dir(crate_dir)),
+            false => None,
+        };
+        if let Some(target_dir) = &isolated_target_dir {
+            let value = target_dir.to_string_lossy().to_string();
+            let route_commands = routes.iter_mut().flat_map(|route| route.commands.iter_mut());
+            let others = on_success.iter_mut().chain(on_failure.iter_mut()).chain(schedule.iter_mut());
+            for cmd in commands.iter_mut().chain(lockfile_commands.iter_mut()).chain(route_commands).chain(others) {
+                if !cmd.env.iter().any(|(key, _)| key == "CARGO_TARGET_DIR") {
+                    cmd.env.push(("CARGO_TARGET_DIR".into(), value.clone()));
+                }
+            }
+        }
+
+        Ok(Settings {
+            config_path,
+            profile,
+            preset,
+            verbose,
+            color,
+            quiet,
+            delay: Duration::from_millis(delay_ms),
+            debounce,
+            max_wait,
+            min_interval,
+            poll,
+            backend,
+            follow_symlinks,
+            watch,
+            ext,
+            tracked_only,
+            max_file_size,
+            trigger_file,
+            includes,
+            include,
+            no_op,
+            no_op_globs,
+            run_first,
+            once,
+            restart_on_change,
+            shell,
+            ignores,
+            editor_ignore,
+            ignore: config.ignore,
+            commands,
+            lockfile_commands,
+            deny_warnings,
+            nice,
+            pty,
+            ssh,
+            container,
+            prefix,
+            isolated_target_dir,
+            wait_for_lock: args.wait_for_lock || config.wait_for_lock.unwrap_or(false),
+            on_success,
+            on_failure,
+            schedule,
+            routes,
+            keep_going: args.keep_going || config.keep_going.unwrap_or(false),
+            failed_first: args.failed_first || config.failed_first.unwrap_or(false),
+            affected_only: args.affected_only || config.affected_only.unwrap_or(false),
+            targeted_tests: args.targeted_tests || config.targeted_tests.unwrap_or(false),
+            doc_tests: args.doc_tests || config.doc_tests.unwrap_or(false),
+            full_test_every: args.full_test_every.or(config.full_test_every).unwrap_or(10),
+        })
+    }
+}
+
+/// Split the extra arguments for a built-in command like a shell would
+fn split_args(line: Option<&String>) -> Result<Vec<String>, Error> {
+    match line {
+        Some(line) => shell_words::split(line).map_err(|e| Error::CustomCmd(line.clone(), e)),
+        None => Ok(Vec::new()),
+    }
+}
+
+impl std::fmt::Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let config_path = self.config_path.as_ref().map(|path| path.to_string_lossy());
+        writeln!(f, "config file: {}", config_path.as_deref().unwrap_or("none"))?;
+        writeln!(f, "profile: {}", self.profile.as_deref().unwrap_or("none"))?;
+        let preset = self.preset.and_then(|preset| clap::ValueEnum::to_possible_value(&preset));
+        writeln!(f, "preset: {}", preset.as_ref().map_or("none", |preset| preset.get_name()))?;
+        writeln!(f, "verbose: {}", self.verbose)?;
+        writeln!(f, "color: {}", self.color)?;
+        writeln!(f, "quiet: {}", self.quiet)?;
+        writeln!(f, "delay: {} ms", self.delay.as_millis())?;
+        let debounce = clap::ValueEnum::to_possible_value(&self.debounce).expect("No skipped values");
+        writeln!(f, "debounce: {}", debounce.get_name())?;
+        match self.max_wait {
+            Some(max_wait) => writeln!(f, "max wait: {} ms", max_wait.as_millis())?,
+            None => writeln!(f, "max wait: none")?,
+        }
+        match self.min_interval {
+            Some(min_interval) => writeln!(f, "min interval: {} ms", min_interval.as_millis())?,
+            None => writeln!(f, "min interval: none")?,
+        }
+        match self.poll {
+            Some(poll) => writeln!(f, "poll: {} ms", poll.as_millis())?,
+            None => writeln!(f, "poll: no")?,
+        }
+        writeln!(f, "backend: {}", self.backend)?;
+        writeln!(f, "follow symlinks: {}", self.follow_symlinks)?;
+        writeln!(f, "watch: {:?}", self.watch)?;
+        writeln!(f, "ext: {:?}", self.ext)?;
+        writeln!(f, "tracked only: {}", self.tracked_only)?;
+        match self.max_file_size {
+            Some(size) => writeln!(f, "max file size: {}", size)?,
+            None => writeln!(f, "max file size: none")?,
+        }
+        writeln!(f, "trigger file: {}", self.trigger_file.to_string_lossy())?;
+        writeln!(f, "include: {:?}", self.include)?;
+        writeln!(f, "no-op: {:?}", self.no_op_globs)?;
+        writeln!(f, "run first: {}", self.run_first)?;
+        writeln!(f, "once: {}", self.once)?;
+        writeln!(f, "restart on change: {}", self.restart_on_change)?;
+        writeln!(f, "shell: {}", self.shell)?;
+        match self.nice {
+            Some(nice) => writeln!(f, "nice: {}", nice)?,
+            None => writeln!(f, "nice: no")?,
+        }
+        match &self.ssh {
+            Some(ssh) => writeln!(f, "ssh: {}", ssh)?,
+            None => writeln!(f, "ssh: no")?,
+        }
+        match &self.container {
+            Some(container) => writeln!(f, "container: {}", container)?,
+            None => writeln!(f, "container: no")?,
+        }
+        writeln!(f, "deny warnings: {}", self.deny_warnings)?;
+        writeln!(f, "pty: {}", self.pty)?;
+        writeln!(f, "prefix: {}", self.prefix)?;
+        match &self.isolated_target_dir {
+            Some(target_dir) => writeln!(f, "isolated target dir: {}", target_dir.to_string_lossy())?,
+            None => writeln!(f, "isolated target dir: no")?,
+        }
+        writeln!(f, "wait for lock: {}", self.wait_for_lock)?;
+        writeln!(f, "keep going: {}", self.keep_going)?;
+        writeln!(f, "failed first: {}", self.failed_first)?;
+        writeln!(f, "affected only: {}", self.affected_only)?;
+        writeln!(f, "targeted tests: {}, all every {} runs", self.targeted_tests, self.full_test_every)?;
+        writeln!(f, "doc tests: {}", self.doc_tests)?;
+        writeln!(f, "editor ignore: {:?}", self.editor_ignore)?;
+        writeln!(f, "ignore: {:?}", self.ignore)?;
+        writeln!(f, "commands:")?;
+        for cmd in self.commands.iter() {
+            let env = cmd.env.iter().map(|(key, value)| format!("{}={} ", key, shell_words::quote(value)));
+            let args = shell_words::join(&cmd.args);
+            write!(f, "    {}: {}{}{}{}", cmd.name, cwd(cmd), env.collect::<String>(), args, limits(cmd))?;
+            match cmd.depends_on.is_empty() {
+                true => writeln!(f)?,
+                false => writeln!(f, " after {}", cmd.depends_on.join(", "))?,
+            }
+        }
+        if !self.lockfile_commands.is_empty() {
+            writeln!(f, "lockfile commands:")?;
+            for cmd in self.lockfile_commands.iter() {
+                writeln!(f, "    {}: {}{}{}", cmd.name, cwd(cmd), shell_words::join(&cmd.args), limits(cmd))?;
+            }
+        }
+        let others = [("on success", &self.on_success), ("on failure", &self.on_failure), ("schedule", &self.schedule)];
+        for (title, hooks) in others {
+            if !hooks.is_empty() {
+                writeln!(f, "{}:", title)?;
+                for cmd in hooks.iter() {
+                    writeln!(f, "    {}: {}{}{}", cmd.name, cwd(cmd), shell_words::join(&cmd.args), limits(cmd))?;
+                }
+            }
+        }
+        if !self.routes.is_empty() {
+            writeln!(f, "routes:")?;
+            for route in self.routes.iter() {
+                writeln!(f, "    {}:", route.path.to_string_lossy())?;
+                for cmd in route.commands.iter() {
+                    writeln!(f, "        {}: {}{}{}", cmd.name, cwd(cmd), shell_words::join(&cmd.args), limits(cmd))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The timeout, resource limits, retries, tier and schedule of a command for printing after it, if it has any
+fn limits(cmd: &Command) -> String {
+    let mut limits = Vec::new();
+    if let Some(timeout) = cmd.timeout {
+        limits.push(format!("timeout {} s", timeout.as_secs()));
+    }
+    if let Some(memory_limit) = cmd.memory_limit {
+        limits.push(format!("memory {}", Size(memory_limit)));
+    }
+    if let Some(cpu_limit) = cmd.cpu_limit {
+        limits.push(format!("cpus {}", cpu_limit));
+    }
+    if cmd.retries > 0 {
+        limits.push(format!("retries {}", cmd.retries));
+    }
+    if cmd.slow {
+        limits.push("slow".into());
+    }
+    if let Some(every) = cmd.every {
+        limits.push(format!("every {}", Interval(every)));
+    }
+    match limits.is_empty() {
+        true => String::new(),
+        false => format!(" ({})", limits.join(", ")),
+    }
+}
+
+/// The target directory to build in when it is kept apart, inside the one of the workspace so the same rules ignore it
+fn isolated_target_dir(crate_dir: &Path) -> PathBuf {
+    let target_dir = cargo::workspace(crate_dir).target_dir.unwrap_or_else(|| crate_dir.join("target"));
+    target_dir.join("auto-check")
+}
+
+/// Create the commands from the config file that are kept apart from the pipeline
+fn to_commands(commands: &[CommandConfig], shell: bool) -> Result<Vec<Command>, Error> {
+    commands
+        .iter()
+        .map(|cmd| cmd.to_command(shell).map_err(|e| Error::CustomCmd(format!("{:?}", cmd), e)))
+        .collect()
+}
+
+/// The directory a command runs in for printing before it, if it doesn't run in the crate directory
+fn cwd(cmd: &Command) -> String {
+    match &cmd.cwd {
+        Some(cwd) => format!("cd {} && ", shell_words::quote(&cwd.to_string_lossy())),
+        None => String::new(),
+    }
+}
+
+/// Make sure the directories the commands run in exist, since failing to start them looks like a missing program
+fn check_cwd(commands: &[Command], crate_dir: &Path) -> Result<(), Error> {
+    for cmd in commands.iter() {
+        if let Some(cwd) = &cmd.cwd {
+            if !crate_dir.join(cwd).is_dir() {
+                return Err(Error::MissingCwd(cmd.name.clone(), cwd.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Make sure the commands depend on commands in the pipeline, and can be run in an order where every command comes
+/// after the ones it depends on
+///
+/// The commands are taken in that order as long as there is one left that all its dependencies came before, and the
+/// ones left over after that depend on each other.
+fn check_dependencies(commands: &[Command]) -> Result<(), Error> {
+    for name in commands.iter().flat_map(|cmd| cmd.depends_on.iter()) {
+        if !commands.iter().any(|cmd| &cmd.name == name) {
+            return Err(Error::UnknownCommand(name.clone()));
+        }
+    }
+    let mut left: Vec<&Command> = commands.iter().collect();
+    while let Some(index) = left
+        .iter()
+        .position(|cmd| !cmd.depends_on.iter().any(|name| left.iter().any(|other| &other.name == name)))
+    {
+        left.remove(index);
+    }
+    match left.is_empty() {
+        true => Ok(()),
+        false => Err(Error::DependencyCycle(left.iter().map(|cmd| cmd.name.clone()).collect())),
+    }
+}
+
+/// Make a path absolute the same way canonicalize does, except that the file doesn't have to exist yet
+fn absolute_path(path: &Path) -> PathBuf {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if dir.as_os_str().is_empty() => (Path::new("."), name),
+        (Some(dir), Some(name)) => (dir, name),
+        _ => return path.into(),
+    };
+    dir.canonicalize().map(|dir| dir.join(name)).unwrap_or_else(|_| path.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(name: &str, depends_on: &[&str]) -> Command {
+        let mut cmd = Command::named(name, vec!["cargo".into(), name.into()]);
+        cmd.depends_on = depends_on.iter().map(|name| name.to_string()).collect();
+        cmd
+    }
+
+    #[test]
+    fn parse_interval() {
+        let cases = [
+            ("90", Some(90)),
+            (" 30s ", Some(30)),
+            ("15m", Some(15 * 60)),
+            ("24H", Some(24 * 3600)),
+            ("2d", Some(2 * 24 * 3600)),
+            ("1w", Some(7 * 24 * 3600)),
+            ("0", None),
+            ("0h", None),
+            ("", None),
+            ("h", None),
+            ("-1s", None),
+            ("1.5h", None),
+            ("10y", None),
+            ("18446744073709551616", None),
+            ("18446744073709551615w", None),
+        ];
+        for (text, secs) in cases {
+            let interval = text.parse::<Interval>().ok();
+            assert_eq!(interval, secs.map(|secs| Interval(Duration::from_secs(secs))), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parse_size() {
+        let cases = [
+            ("0", Some(0)),
+            ("512", Some(512)),
+            (" 100B ", Some(100)),
+            ("10k", Some(10 << 10)),
+            ("10M", Some(10 << 20)),
+            ("2MiB", Some(2 << 20)),
+            ("1GB", Some(1 << 30)),
+            ("", None),
+            ("M", None),
+            ("-1", None),
+            ("1.5M", None),
+            ("10T", None),
+            ("18446744073709551616", None),
+            ("18446744073709551615K", None),
+        ];
+        for (text, bytes) in cases {
+            assert_eq!(text.parse::<Size>().ok(), bytes.map(Size), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn dependencies_in_order() {
+        let commands = [
+            command("check", &[]),
+            command("clippy", &["check"]),
+            command("test", &["check"]),
+            command("doc", &["clippy", "test"]),
+        ];
+        assert!(check_dependencies(&commands).is_ok());
+    }
+
+    #[test]
+    fn dependency_cycle() {
+        let commands = [
+            command("check", &[]),
+            command("clippy", &["test"]),
+            command("test", &["doc"]),
+            command("doc", &["clippy"]),
+        ];
+        match check_dependencies(&commands) {
+            Err(Error::DependencyCycle(names)) => assert_eq!(names, ["clippy", "test", "doc"]),
+            result => panic!("Expected a cycle, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let commands = [command("check", &[]), command("test", &["build"])];
+        match check_dependencies(&commands) {
+            Err(Error::UnknownCommand(name)) => assert_eq!(name, "build"),
+            result => panic!("Expected an unknown command, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::runner::{self, Run};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How the last run that finished went in each crate directory, which is what quitting reports
+static LAST_RUNS: Mutex<Vec<(PathBuf, Run)>> = Mutex::new(Vec::new());
+
+/// The signals that make auto-check-rs quit, which are Ctrl-C, a closed terminal and being asked to
+#[cfg(unix)]
+const SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// The end of the pipe the signal handler writes the signal to, since hardly anything else is safe to do in it
+#[cfg(unix)]
+static PIPE: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(-1);
+
+/// Stop the running commands before quitting on Ctrl-C or when asked to by another process
+///
+/// The commands run in their own process groups, so they don't get the Ctrl-C from the terminal.
+#[cfg(unix)]
+pub fn handle() {
+    use std::sync::atomic::Ordering;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        log::warn!("Failed to create the pipe for signals: {}", std::io::Error::last_os_error());
+        return;
+    }
+    let [read_fd, write_fd] = fds;
+    PIPE.store(write_fd, Ordering::Relaxed);
+
+    std::thread::spawn(move || loop {
+        let mut signal = 0u8;
+        match unsafe { libc::read(read_fd, &mut signal as *mut u8 as *mut libc::c_void, 1) } {
+            1 => {
+                log::debug!("Quitting after signal {}", signal);
+                quit(128 + signal as i32);
+            },
+            _ if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {},
+            _ => return,
+        }
+    });
+
+    for &signal in SIGNALS {
+        unsafe { libc::signal(signal, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    let fd = PIPE.load(std::sync::atomic::Ordering::Relaxed);
+    let signal = signal as u8;
+    unsafe { libc::write(fd, &signal as *const u8 as *const libc::c_void, 1) };
+}
+
+#[cfg(not(unix))]
+pub fn handle() {}
+
+/// Remember how the last run in the crate directory went, where a stopped run doesn't say anything new
+pub fn record(crate_dir: &Path, run: &Run) {
+    if *run == Run::Stopped {
+        return;
+    }
+    let mut last_runs = LAST_RUNS.lock().expect("Last runs poisoned");
+    match last_runs.iter_mut().find(|(dir, _)| dir == crate_dir) {
+        Some((_, last)) => *last = run.clone(),
+        None => last_runs.push((crate_dir.into(), run.clone())),
+    }
+}
+
+/// Stop the running commands and exit with the status of the last runs, or with the code if no run has finished
+///
+/// The status is 1 if the last run in any of the crate directories failed. How the last runs went is logged, since the
+/// output of the run may be far up by then.
+pub fn quit(code: i32) -> ! {
+    runner::stop_all();
+    let last_runs = LAST_RUNS.lock().expect("Last runs poisoned");
+    let mut code = if last_runs.is_empty() { code } else { 0 };
+    for (crate_dir, run) in last_runs.iter() {
+        let place = match last_runs.len() {
+            1 => String::new(),
+            _ => format!(" in {}", crate_dir.to_string_lossy()),
+        };
+        match run {
+            Run::Failed(names) => {
+                log::error!("The last run{} failed: {}", place, names.join(", "));
+                code = 1;
+            },
+            _ => log::info!("The last run{} passed", place),
+        }
+    }
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    std::process::exit(code)
+}
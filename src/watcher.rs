@@ -0,0 +1,396 @@
+use notify::event::{EventKind, MetadataKind, ModifyKind, RenameMode};
+use crate::input::Input;
+use crate::settings::Backend;
+use notify::{PollWatcher, RecursiveMode};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// A change after the raw events from notify have been debounced
+#[derive(Debug)]
+pub enum DebouncedEvent {
+    Create(PathBuf),
+    Write(PathBuf),
+    Remove(PathBuf),
+    Rename(PathBuf, PathBuf),
+    /// Only the metadata of a file changed, like the modification time after a touch
+    Metadata(PathBuf),
+    /// Events may have been lost, and the state of the watched directories has to be checked again
+    Rescan,
+    /// Something was typed in the terminal, which is sent through the same channel to wake up the main loop
+    Input(Input),
+    Error(notify::Error),
+}
+
+impl DebouncedEvent {
+    fn path(&self) -> Option<&Path> {
+        match self {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Remove(path)
+            | DebouncedEvent::Metadata(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Watches directories and sends debounced events to a channel
+pub struct Watcher {
+    inner: Box<dyn notify::Watcher + Send>,
+    /// Everything that is watched, to be able to stop watching all the directories below a directory
+    watched: BTreeSet<PathBuf>,
+    /// Watch the directories that symbolic links point to as if they were in the tree
+    pub follow_symlinks: bool,
+}
+
+impl Watcher {
+    /// Create a watcher that waits until a path has been quiet for `debounce` before sending its events
+    ///
+    /// The files are checked for changes with the `poll` interval if given, instead of using the native events from
+    /// the file system. Those are not reliable on network file systems and in some containers. Otherwise the events
+    /// come from the backend, which fails if it isn't available on this platform.
+    pub fn new(
+        tx: Sender<DebouncedEvent>,
+        debounce: Duration,
+        backend: Backend,
+        poll: Option<Duration>,
+    ) -> notify::Result<Watcher> {
+        let (raw_tx, raw_rx) = std::sync::mpsc::channel();
+        let inner: Box<dyn notify::Watcher + Send> = match (poll, backend) {
+            (Some(interval), _) => {
+                Box::new(PollWatcher::new(raw_tx, notify::Config::default().with_poll_interval(interval))?)
+            },
+            (None, Backend::Auto) => Box::new(notify::recommended_watcher(raw_tx)?),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            (None, Backend::Inotify) => native::<notify::INotifyWatcher>(raw_tx)?,
+            #[cfg(all(target_os = "macos", not(feature = "macos_kqueue")))]
+            (None, Backend::Fsevents) => native::<notify::FsEventWatcher>(raw_tx)?,
+            #[cfg(any(
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd",
+                all(target_os = "macos", feature = "macos_kqueue")
+            ))]
+            (None, Backend::Kqueue) => native::<notify::KqueueWatcher>(raw_tx)?,
+            #[cfg(target_os = "windows")]
+            (None, Backend::Windows) => native::<notify::ReadDirectoryChangesWatcher>(raw_tx)?,
+            (None, backend) => {
+                let message = format!("the {} backend is not available on this platform", backend);
+                return Err(notify::Error::generic(&message));
+            },
+        };
+        std::thread::spawn(move || Debouncer::new(tx, debounce).run(raw_rx));
+        Ok(Watcher {
+            inner,
+            watched: BTreeSet::new(),
+            follow_symlinks: false,
+        })
+    }
+
+    /// Watch a single file
+    ///
+    /// This watches the directory it is in, since editors often save a file by replacing it with another one and that
+    /// would not be seen by a watch on the file itself. The events for the other files in the directory are sent too.
+    pub fn watch_file(&mut self, path: &Path) -> notify::Result<()> {
+        let dir = path.parent().unwrap_or(path);
+        log::trace!("Watching {} for {}", dir.to_string_lossy(), path.to_string_lossy());
+        self.inner.watch(dir, RecursiveMode::NonRecursive)?;
+        self.watched.insert(dir.into());
+        Ok(())
+    }
+
+    /// Watch a directory and all the directories below it, except the ones that are ignored
+    ///
+    /// Every directory is watched on its own, so nothing is registered for large ignored trees like `target/`. New
+    /// directories have to be passed to this as they are created. Returns the files that were found in the
+    /// directories, since anything created before the watches were in place is never reported.
+    ///
+    /// Symbolic links to directories are only followed when enabled, and then only to directories that are not
+    /// already watched, so links back up the tree don't make it go around in circles.
+    pub fn watch_tree<F: Fn(&Path) -> bool>(&mut self, dir: &Path, ignored: F) -> notify::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            if !dir.is_dir() || ignored(&dir) {
+                continue;
+            }
+            if self.follow_symlinks {
+                let real = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+                if (real != dir && self.watched.contains(&real)) || !visited.insert(real) {
+                    log::debug!("Not following {} again", dir.to_string_lossy());
+                    continue;
+                }
+            }
+
+            log::trace!("Watching {}", dir.to_string_lossy());
+            self.inner.watch(&dir, RecursiveMode::NonRecursive)?;
+            self.watched.insert(dir.clone());
+
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    log::warn!("Failed to read {}: {}", dir.to_string_lossy(), e);
+                    continue;
+                },
+            };
+            for entry in entries.flatten() {
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => dirs.push(entry.path()),
+                    Ok(file_type) if file_type.is_file() => files.push(entry.path()),
+                    Ok(file_type) if file_type.is_symlink() && self.follow_symlinks && entry.path().is_dir() => {
+                        dirs.push(entry.path())
+                    },
+                    _ => {},
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Stop watching a path and everything below it, after it was moved or removed
+    ///
+    /// The native watches follow a directory when it is moved, but keep reporting the old paths for it. They have to
+    /// be removed before the directory is watched again at its new path.
+    pub fn unwatch_tree(&mut self, path: &Path) {
+        let below: Vec<PathBuf> = self
+            .watched
+            .range(path.to_path_buf()..)
+            .take_while(|watched| watched.starts_with(path))
+            .cloned()
+            .collect();
+        for watched in below {
+            log::trace!("No longer watching {}", watched.to_string_lossy());
+            // This fails if the native watch is already gone with the directory, which is fine
+            let _ = self.inner.unwatch(&watched);
+            self.watched.remove(&watched);
+        }
+    }
+}
+
+/// Create one of the native watchers
+fn native<W: notify::Watcher + Send + 'static>(
+    raw_tx: Sender<notify::Result<notify::Event>>,
+) -> notify::Result<Box<dyn notify::Watcher + Send>> {
+    Ok(Box::new(W::new(raw_tx, notify::Config::default())?))
+}
+
+/// Collects the raw events and coalesces the ones for the same path
+struct Debouncer {
+    tx: Sender<DebouncedEvent>,
+    debounce: Duration,
+    /// The events waiting to be sent, and when they were last updated
+    pending: Vec<(Instant, DebouncedEvent)>,
+    /// The source paths of renames that are waiting for the destination, by the cookie from notify
+    renames: HashMap<usize, (Instant, PathBuf)>,
+}
+
+impl Debouncer {
+    fn new(tx: Sender<DebouncedEvent>, debounce: Duration) -> Debouncer {
+        Debouncer {
+            tx,
+            debounce,
+            pending: Vec::new(),
+            renames: HashMap::new(),
+        }
+    }
+
+    fn run(mut self, raw_rx: Receiver<notify::Result<notify::Event>>) {
+        loop {
+            let next_deadline = self
+                .pending
+                .iter()
+                .map(|(time, _)| *time)
+                .chain(self.renames.values().map(|(time, _)| *time))
+                .min()
+                .map(|time| time + self.debounce);
+            let timeout = match next_deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => Duration::from_secs(3600),
+            };
+
+            match raw_rx.recv_timeout(timeout) {
+                Ok(Ok(event)) => self.add(event),
+                Ok(Err(e)) => self.push(DebouncedEvent::Error(e)),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if !self.flush() {
+                return;
+            }
+        }
+    }
+
+    fn add(&mut self, event: notify::Event) {
+        log::trace!("Raw event: {:?}", event);
+
+        if event.need_rescan() {
+            self.push(DebouncedEvent::Rescan);
+            return;
+        }
+
+        let mut paths = event.paths.into_iter();
+        match event.kind {
+            EventKind::Create(_) => paths.for_each(|path| self.push(DebouncedEvent::Create(path))),
+            EventKind::Remove(_) => paths.for_each(|path| self.push(DebouncedEvent::Remove(path))),
+            // The polling watcher only sees that the modification time changed, and for directories that only happens
+            // when the entries in them are created or removed, which are reported on their own
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)) => paths
+                .filter(|path| !path.is_dir())
+                .for_each(|path| self.push(DebouncedEvent::Write(path))),
+            EventKind::Modify(ModifyKind::Metadata(_)) => paths
+                .filter(|path| !path.is_dir())
+                .for_each(|path| self.push(DebouncedEvent::Metadata(path))),
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => match (event.attrs.tracker(), paths.next()) {
+                (Some(cookie), Some(from)) => {
+                    self.renames.insert(cookie, (Instant::now(), from));
+                },
+                (None, Some(from)) => self.push(DebouncedEvent::Remove(from)),
+                _ => {},
+            },
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                let from = event.attrs.tracker().and_then(|cookie| self.renames.remove(&cookie));
+                match (from, paths.next()) {
+                    (Some((_, from)), Some(to)) => self.push(DebouncedEvent::Rename(from, to)),
+                    (None, Some(to)) => self.push(DebouncedEvent::Create(to)),
+                    _ => {},
+                }
+            },
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                // Backends with cookies already reported this as a From and To pair
+                if event.attrs.tracker().is_none() {
+                    if let (Some(from), Some(to)) = (paths.next(), paths.next()) {
+                        self.push(DebouncedEvent::Rename(from, to));
+                    }
+                }
+            },
+            EventKind::Modify(ModifyKind::Name(_)) => paths.for_each(|path| {
+                // Without knowing which side of the rename this is, check what is there now
+                let event = if path.exists() {
+                    DebouncedEvent::Create(path)
+                } else {
+                    DebouncedEvent::Remove(path)
+                };
+                self.push(event)
+            }),
+            EventKind::Modify(_) => paths.for_each(|path| self.push(DebouncedEvent::Write(path))),
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => {},
+        }
+    }
+
+    /// Add an event, merging it with a pending event for the same path
+    fn push(&mut self, event: DebouncedEvent) {
+        use DebouncedEvent::*;
+
+        // A file written somewhere else and moved into place, like editors and formatters do, is a single change
+        if let Rename(from, to) = &event {
+            let created = self
+                .pending
+                .iter()
+                .position(|(_, pending)| matches!(pending, Create(path) if path == from));
+            if let Some(index) = created {
+                self.pending.remove(index);
+                self.push(if to.is_dir() { Create(to.clone()) } else { Write(to.clone()) });
+                return;
+            }
+        }
+
+        let now = Instant::now();
+        let existing = event
+            .path()
+            .and_then(|path| self.pending.iter().position(|(_, pending)| pending.path() == Some(path)));
+
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                self.pending.push((now, event));
+                return;
+            },
+        };
+
+        let (_, previous) = self.pending.remove(index);
+        let merged = match (previous, event) {
+            // A file that was created and removed again within the window never existed as far as we care
+            (Create(_), Remove(_)) => None,
+            (Create(path), Write(_)) => Some(Create(path)),
+            (Remove(path), Create(_)) => Some(Write(path)),
+            // Anything else that happened to the file says more than the metadata changing
+            (previous, Metadata(_)) => Some(previous),
+            (_, event) => Some(event),
+        };
+        if let Some(merged) = merged {
+            self.pending.push((now, merged));
+        }
+    }
+
+    /// Send the events that have been quiet for long enough, returns false if the receiver is gone
+    fn flush(&mut self) -> bool {
+        let now = Instant::now();
+        let debounce = self.debounce;
+
+        // A rename without a destination in the watched directories is the same as a removal
+        let expired: Vec<usize> = self
+            .renames
+            .iter()
+            .filter(|(_, (time, _))| now.duration_since(*time) >= debounce)
+            .map(|(cookie, _)| *cookie)
+            .collect();
+        for cookie in expired {
+            if let Some((_, from)) = self.renames.remove(&cookie) {
+                self.push(DebouncedEvent::Remove(from));
+            }
+        }
+
+        let (ready, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(time, _)| now.duration_since(*time) >= debounce);
+        self.pending = pending;
+
+        let ready: Vec<(Instant, DebouncedEvent)> = ready;
+        ready.into_iter().all(|(_, event)| self.tx.send(event).is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    /// Push the events and send everything that is pending
+    fn debounce(events: Vec<DebouncedEvent>) -> Vec<DebouncedEvent> {
+        let (tx, rx) = channel();
+        let mut debouncer = Debouncer::new(tx, Duration::ZERO);
+        events.into_iter().for_each(|event| debouncer.push(event));
+        assert!(debouncer.flush());
+        rx.try_iter().collect()
+    }
+
+    #[test]
+    fn created_and_removed_cancel_out() {
+        let events = debounce(vec![
+            DebouncedEvent::Create("src/new.rs".into()),
+            DebouncedEvent::Remove("src/new.rs".into()),
+        ]);
+        assert!(events.is_empty(), "{:?}", events);
+    }
+
+    #[test]
+    fn removed_and_created_is_written() {
+        let events = debounce(vec![
+            DebouncedEvent::Remove("src/lib.rs".into()),
+            DebouncedEvent::Create("src/lib.rs".into()),
+        ]);
+        assert!(matches!(events.as_slice(), [DebouncedEvent::Write(path)] if path == Path::new("src/lib.rs")));
+    }
+
+    #[test]
+    fn created_and_renamed_is_written() {
+        let events = debounce(vec![
+            DebouncedEvent::Create("src/.lib.rs.tmp".into()),
+            DebouncedEvent::Rename("src/.lib.rs.tmp".into(), "src/lib.rs".into()),
+        ]);
+        assert!(matches!(events.as_slice(), [DebouncedEvent::Write(path)] if path == Path::new("src/lib.rs")));
+    }
+}